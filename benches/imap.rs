@@ -0,0 +1,98 @@
+//! Benchmarks of the IMAP coroutine hot path.
+//!
+//! The greeting and the STARTTLS response are fed in chunks of
+//! various sizes, from byte-by-byte reads to reads larger than the
+//! whole exchange, so that the cost of resuming the coroutine and
+//! scanning the buffer can be compared between changes.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::{drive, MockStream};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use io_starttls::imap::UpgradeTls;
+
+/// The sizes of the exchanged data, from the common small greeting
+/// to unusually large ones.
+const SIZES: [usize; 3] = [64, 1024, 16 * 1024];
+
+/// The sizes of the chunks returned by every read.
+const CHUNK_SIZES: [usize; 3] = [1, 64, 4096];
+
+/// Builds an untagged line of the given size, terminator included.
+fn untagged_line(size: usize) -> Vec<u8> {
+    let mut line = b"* OK ".to_vec();
+    line.resize(size.saturating_sub(2).max(line.len()), b'x');
+    line.extend_from_slice(b"\r\n");
+    line
+}
+
+/// Splits the given bytes into chunks of the given size.
+fn chunks(bytes: &[u8], size: usize) -> Vec<Vec<u8>> {
+    bytes.chunks(size).map(<[u8]>::to_vec).collect()
+}
+
+/// Measures the discarding of greetings of various sizes.
+fn discard_greeting(c: &mut Criterion) {
+    let mut group = c.benchmark_group("discard_greeting");
+
+    for size in SIZES {
+        let mut bytes = untagged_line(size);
+        bytes.extend_from_slice(b"NGC6543 OK begin TLS now\r\n");
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        for chunk_size in CHUNK_SIZES {
+            let id = BenchmarkId::new(format!("{size}B"), format!("{chunk_size}B chunks"));
+
+            group.bench_function(id, |b| {
+                b.iter_batched(
+                    || MockStream::new(chunks(&bytes, chunk_size)),
+                    |mut stream| {
+                        let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+                        drive(&mut starttls, &mut stream).unwrap()
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// Measures the discarding of STARTTLS responses preceded by
+/// untagged data of various sizes.
+fn discard_response(c: &mut Criterion) {
+    let mut group = c.benchmark_group("discard_response");
+
+    for size in SIZES {
+        let mut bytes = Vec::new();
+
+        while bytes.len() < size {
+            bytes.extend(untagged_line(64));
+        }
+
+        bytes.extend_from_slice(b"NGC6543 OK begin TLS now\r\n");
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        for chunk_size in CHUNK_SIZES {
+            let id = BenchmarkId::new(format!("{size}B"), format!("{chunk_size}B chunks"));
+
+            group.bench_function(id, |b| {
+                b.iter_batched(
+                    || MockStream::new(chunks(&bytes, chunk_size)),
+                    |mut stream| {
+                        let mut starttls = UpgradeTls::new();
+                        drive(&mut starttls, &mut stream).unwrap()
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, discard_greeting, discard_response);
+criterion_main!(benches);
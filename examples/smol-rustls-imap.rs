@@ -0,0 +1,70 @@
+#![cfg(all(feature = "imap", feature = "futures"))]
+
+use std::{
+    env,
+    io::{stdin, stdout, Write as _},
+    sync::Arc,
+};
+
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig},
+    TlsConnector,
+};
+use io_starttls::imap::UpgradeTls;
+use log::info;
+use rustls_platform_verifier::ConfigVerifierExt;
+use smol::net::TcpStream;
+
+fn main() {
+    if let Err(_) = env::var("RUST_LOG") {
+        env::set_var("RUST_LOG", "debug");
+    }
+
+    env_logger::init();
+
+    let host = match env::var("HOST") {
+        Ok(host) => host,
+        Err(_) => prompt("TCP server host?"),
+    };
+
+    let port: u16 = match env::var("PORT") {
+        Ok(port) => port.parse().unwrap(),
+        Err(_) => prompt("TCP server port?").parse().unwrap(),
+    };
+
+    smol::block_on(async {
+        let mut tcp = TcpStream::connect((host.as_str(), port)).await.unwrap();
+
+        UpgradeTls::new()
+            .with_discard_greeting(true)
+            .run_futures(&mut tcp)
+            .await
+            .unwrap();
+
+        info!("upgrade current TCP stream to TLS");
+        let config = ClientConfig::with_platform_verifier();
+        let server_name = ServerName::try_from(host.clone()).unwrap();
+        let connector = TlsConnector::from(Arc::new(config));
+        let mut tls = connector.connect(server_name, tcp).await.unwrap();
+
+        info!("send NOOP command via TLS");
+        tls.write_all(b"A NOOP\r\n").await.unwrap();
+
+        let mut buffer = [0; 1024];
+        let n = tls.read(&mut buffer).await.unwrap();
+
+        let bytes = String::from_utf8_lossy(&buffer[..n]);
+        info!("receive NOOP response via TLS: {bytes:?}");
+    })
+}
+
+fn prompt(message: &str) -> String {
+    print!("{message} ");
+    stdout().flush().unwrap();
+
+    let mut line = String::new();
+    stdin().read_line(&mut line).unwrap();
+
+    line.trim().to_owned()
+}
@@ -0,0 +1,71 @@
+#![cfg(feature = "imap")]
+
+use std::{
+    env,
+    io::{stdin, stdout, Write as _},
+    net::TcpStream,
+};
+
+use io_starttls::imap::{self, UpgradeTls};
+use io_stream::runtimes::std::handle;
+use log::info;
+use openssl::ssl::{SslConnector, SslMethod};
+
+fn main() {
+    if let Err(_) = env::var("RUST_LOG") {
+        env::set_var("RUST_LOG", "debug");
+    }
+
+    env_logger::init();
+
+    let host = match env::var("HOST") {
+        Ok(host) => host,
+        Err(_) => prompt("TCP server host?"),
+    };
+
+    let port: u16 = match env::var("PORT") {
+        Ok(port) => port.parse().unwrap(),
+        Err(_) => prompt("TCP server port?").parse().unwrap(),
+    };
+
+    let mut tcp = TcpStream::connect((host.as_str(), port)).unwrap();
+
+    let mut input = None;
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let result = loop {
+        match starttls.resume(input) {
+            Ok(result) => break result,
+            Err(io) => input = Some(handle(&mut tcp, io).unwrap()),
+        }
+    };
+
+    result.unwrap();
+
+    info!("upgrade current TCP stream to TLS");
+    let connector = SslConnector::builder(SslMethod::tls()).unwrap().build();
+    let mut tls = connector.connect(&host, tcp).unwrap();
+
+    info!("send NOOP command via TLS");
+    let mut noop = imap::noop();
+    let mut input = None;
+
+    let status = loop {
+        match noop.resume(input) {
+            Ok(result) => break result.unwrap(),
+            Err(io) => input = Some(handle(&mut tls, io).unwrap()),
+        }
+    };
+
+    info!("receive NOOP status via TLS: {status:?}");
+}
+
+fn prompt(message: &str) -> String {
+    print!("{message} ");
+    stdout().flush().unwrap();
+
+    let mut line = String::new();
+    stdin().read_line(&mut line).unwrap();
+
+    line.trim().to_owned()
+}
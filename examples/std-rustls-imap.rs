@@ -7,11 +7,8 @@ use std::{
     sync::Arc,
 };
 
-use io_starttls::imap::UpgradeTls;
-use io_stream::{
-    coroutines::{Read, Write},
-    runtimes::std::handle,
-};
+use io_starttls::imap::{self, UpgradeTls};
+use io_stream::runtimes::std::handle;
 use log::info;
 use rustls::{ClientConfig, ClientConnection, StreamOwned};
 use rustls_platform_verifier::ConfigVerifierExt;
@@ -38,9 +35,14 @@ fn main() {
     let mut input = None;
     let mut starttls = UpgradeTls::new().with_discard_greeting(true);
 
-    while let Err(io) = starttls.resume(input) {
-        input = Some(handle(&mut tcp, io).unwrap());
-    }
+    let result = loop {
+        match starttls.resume(input) {
+            Ok(result) => break result,
+            Err(io) => input = Some(handle(&mut tcp, io).unwrap()),
+        }
+    };
+
+    result.unwrap();
 
     info!("upgrade current TCP stream to TLS");
     let config = ClientConfig::with_platform_verifier();
@@ -49,25 +51,17 @@ fn main() {
     let mut tls = StreamOwned::new(conn, tcp);
 
     info!("send NOOP command via TLS");
+    let mut noop = imap::noop();
     let mut input = None;
-    let mut write = Write::new(b"A NOOP\r\n".to_vec());
-
-    while let Err(io) = write.resume(input) {
-        input = Some(handle(&mut tls, io).unwrap());
-    }
-
-    let mut input = None;
-    let mut read = Read::default();
 
-    let output = loop {
-        match read.resume(input) {
-            Ok(output) => break output,
+    let status = loop {
+        match noop.resume(input) {
+            Ok(result) => break result.unwrap(),
             Err(io) => input = Some(handle(&mut tls, io).unwrap()),
         }
     };
 
-    let bytes = String::from_utf8_lossy(output.bytes());
-    info!("receive NOOP response via TLS: {bytes:?}");
+    info!("receive NOOP status via TLS: {status:?}");
 }
 
 fn prompt(message: &str) -> String {
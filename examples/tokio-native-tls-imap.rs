@@ -37,9 +37,14 @@ async fn main() {
     let mut input = None;
     let mut starttls = UpgradeTls::new().with_discard_greeting(true);
 
-    while let Err(io) = starttls.resume(input) {
-        input = Some(handle(&mut tcp, io).await.unwrap());
-    }
+    let result = loop {
+        match starttls.resume(input) {
+            Ok(result) => break result,
+            Err(io) => input = Some(handle(&mut tcp, io).await.unwrap()),
+        }
+    };
+
+    result.unwrap();
 
     info!("upgrade current TCP stream to TLS");
     let connector = native_tls::TlsConnector::new().unwrap();
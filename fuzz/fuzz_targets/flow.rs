@@ -0,0 +1,58 @@
+//! Fuzz target feeding random chunks to the sans-io [`Flow`] of the
+//! IMAP and SMTP coroutines.
+//!
+//! Unlike the `upgrade` target, chunks are fed to the flow directly,
+//! including empty ones out of order. The flow must never panic, and
+//! must always complete or ask for more bytes.
+//!
+//! Run it with `cargo fuzz run flow`.
+//!
+//! [`Flow`]: io_starttls::sans_io::Flow
+
+#![no_main]
+
+use io_starttls::{
+    imap,
+    sans_io::{Flow, Step},
+    smtp, StartTls,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, data)) = data.split_first() else {
+        return;
+    };
+
+    match selector % 2 {
+        0 => {
+            let flow = imap::UpgradeTls::new()
+                .with_discard_greeting(true)
+                .with_check_capability(true)
+                .into_flow();
+            run(flow, data);
+        }
+        _ => {
+            let flow = smtp::UpgradeTls::new()
+                .with_discard_greeting(true)
+                .with_drain_lines(true)
+                .into_flow();
+            run(flow, data);
+        }
+    }
+});
+
+/// Feeds the given bytes to the given flow, chunk by chunk.
+fn run<P: StartTls>(mut flow: Flow<P>, mut data: &[u8]) {
+    let mut step = flow.advance(&[]);
+
+    while let Some((len, rest)) = data.split_first() {
+        let len = (*len as usize % 32).min(rest.len());
+        let (chunk, rest) = rest.split_at(len);
+        data = rest;
+
+        step = match step {
+            Step::NeedMore | Step::Write(_) => flow.advance(chunk),
+            Step::Done(_) => return,
+        };
+    }
+}
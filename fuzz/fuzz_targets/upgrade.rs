@@ -0,0 +1,150 @@
+//! Fuzz target driving the [`Upgrade`] coroutines of all protocols
+//! against a scripted server.
+//!
+//! The first byte selects the protocol, the remaining ones are split
+//! into chunks returned by successive reads. The coroutine must never
+//! panic, and must always terminate once the server runs out of
+//! bytes.
+//!
+//! Run it with `cargo fuzz run upgrade`.
+
+#![no_main]
+
+use std::collections::VecDeque;
+
+use io_starttls::{
+    ftp, imap, irc, ldap,
+    lmtp::{self, Lmtp},
+    mysql, nntp, pop3, postgres, sieve, smtp, xmpp, StartTls, Upgrade,
+};
+use io_stream::{Io, Output};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, data)) = data.split_first() else {
+        return;
+    };
+
+    let server = Server::new(data);
+
+    match selector % 14 {
+        0 => run(imap::UpgradeTls::new().with_discard_greeting(true), server),
+        1 => run(
+            imap::UpgradeTls::new()
+                .with_discard_greeting(true)
+                .with_check_capability(true)
+                .with_drain_lines(true),
+            server,
+        ),
+        2 => run(
+            smtp::UpgradeTls::new()
+                .with_discard_greeting(true)
+                .with_prelude(vec![b"EHLO localhost\r\n".to_vec()]),
+            server,
+        ),
+        3 => run(
+            lmtp::UpgradeTls::from_protocol(Lmtp::default().with_lhlo("localhost"))
+                .with_discard_greeting(true),
+            server,
+        ),
+        4 => run(
+            pop3::UpgradeTls::new()
+                .with_discard_greeting(true)
+                .with_check_capability(true),
+            server,
+        ),
+        5 => run(ftp::UpgradeTls::new().with_discard_greeting(true), server),
+        6 => run(irc::UpgradeTls::new().with_discard_greeting(true), server),
+        7 => run(nntp::UpgradeTls::new().with_discard_greeting(true), server),
+        8 => run(sieve::UpgradeTls::new().with_discard_greeting(true), server),
+        9 => run(xmpp::UpgradeTls::new().with_discard_greeting(true), server),
+        10 => run(ldap::UpgradeTls::new(), server),
+        11 => run(mysql::UpgradeTls::new().with_discard_greeting(true), server),
+        12 => run(postgres::UpgradeTls::new(), server),
+        _ => run(
+            imap::UpgradeTls::new()
+                .with_discard_greeting(true)
+                .with_strict_ascii(true)
+                .with_strict_buffer(true)
+                .with_max_buffer(64),
+            server,
+        ),
+    }
+});
+
+/// Runs the given coroutine to completion against the given server.
+fn run<P: StartTls>(mut upgrade: Upgrade<P>, mut server: Server) {
+    // every I/O request either consumes a chunk or writes a command,
+    // and only a few commands are written, so the number of requests
+    // is bounded by the number of chunks
+    let max = 2 * server.chunks.len() + 64;
+    let mut input = None;
+
+    for _ in 0..max {
+        match upgrade.resume(input.take()) {
+            Ok(_) => return,
+            Err(io) => input = Some(server.handle(io)),
+        }
+    }
+
+    panic!("coroutine did not terminate after {max} I/O requests");
+}
+
+/// The scripted server answering the I/O requests of the coroutine.
+struct Server {
+    chunks: VecDeque<Vec<u8>>,
+}
+
+impl Server {
+    /// Splits the given bytes into chunks.
+    ///
+    /// Each chunk is prefixed by a byte telling its length, so that
+    /// the fuzzer controls how the bytes are spread across reads.
+    fn new(mut data: &[u8]) -> Self {
+        let mut chunks = VecDeque::new();
+
+        while let Some((len, rest)) = data.split_first() {
+            let len = (*len as usize % 32 + 1).min(rest.len());
+            let (chunk, rest) = rest.split_at(len);
+            chunks.push_back(chunk.to_vec());
+            data = rest;
+        }
+
+        Self { chunks }
+    }
+
+    /// Processes the given I/O request.
+    ///
+    /// Once all chunks have been read, reads return zero bytes like a
+    /// closed connection.
+    fn handle(&mut self, io: Io) -> Io {
+        match io {
+            Io::Read(Err(mut buffer)) => {
+                let mut bytes_count = 0;
+
+                if let Some(mut chunk) = self.chunks.pop_front() {
+                    if chunk.len() > buffer.len() {
+                        let rest = chunk.split_off(buffer.len());
+                        self.chunks.push_front(rest);
+                    }
+
+                    buffer[..chunk.len()].copy_from_slice(&chunk);
+                    bytes_count = chunk.len();
+                }
+
+                Io::Read(Ok(Output {
+                    buffer,
+                    bytes_count,
+                }))
+            }
+            Io::Write(Err(buffer)) => {
+                let bytes_count = buffer.len();
+                Io::Write(Ok(Output {
+                    buffer,
+                    bytes_count,
+                }))
+            }
+            io => panic!("unexpected I/O request {io:?}"),
+        }
+    }
+}
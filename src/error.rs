@@ -0,0 +1,120 @@
+//! Module dedicated to the [`UpgradeTlsError`] type.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// The error returned by STARTTLS coroutines once the server
+/// response has been received.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UpgradeTlsError {
+    /// The server explicitly rejected a command, usually the
+    /// STARTTLS one.
+    Rejected {
+        /// The response status, like `NO` for IMAP or `454` for SMTP.
+        status: String,
+        /// The full response line, lossy-decoded from UTF-8.
+        line: String,
+    },
+    /// The server does not advertise the STARTTLS capability, which
+    /// is only reported when capabilities are known before writing
+    /// the STARTTLS command (like with the IMAP or POP3
+    /// `check_capability` option, or from the MySQL handshake).
+    ///
+    /// Nothing has been written to the stream in place of the
+    /// STARTTLS command, so clients implementing an opportunistic TLS
+    /// policy may branch on this error to fall back to implicit TLS
+    /// or to a plain connection.
+    StartTlsNotAdvertised,
+    /// The server sent a response that could not be parsed.
+    InvalidResponse(Vec<u8>),
+    /// The maximum number of reads has been reached before receiving
+    /// the expected line.
+    Timeout,
+    /// The buffer reached its maximum size before receiving the
+    /// expected line.
+    LineTooLong,
+    /// The server sent non-ASCII bytes, which is only reported in
+    /// strict ASCII mode.
+    NonAscii(Vec<u8>),
+    /// The coroutine has been configured with incompatible options.
+    InvalidConfig(String),
+    /// The server sent unexpected bytes after the STARTTLS response,
+    /// which is only reported in strict buffer mode.
+    TrailingBytes(Vec<u8>),
+    /// The server announced that it is closing the connection, like
+    /// with the IMAP `BYE` response. It holds the announcing line,
+    /// lossy-decoded from UTF-8.
+    ServerClosing(String),
+    /// The server closed the connection before sending the expected
+    /// greeting or response.
+    UnexpectedEof,
+    /// The stream accepted none of the bytes of a command, which
+    /// usually means that it has been closed.
+    WriteZero,
+    /// The server rejected the STARTTLS command because the stream
+    /// is already secure, which happens when the coroutine is run
+    /// over an upgraded stream. It holds the rejection line,
+    /// lossy-decoded from UTF-8.
+    AlreadySecure(String),
+}
+
+impl fmt::Display for UpgradeTlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rejected { line, .. } => write!(f, "server rejected STARTTLS: {line}"),
+            Self::StartTlsNotAdvertised => write!(f, "server does not advertise STARTTLS"),
+            Self::InvalidResponse(bytes) => {
+                let bytes = String::from_utf8_lossy(bytes);
+                write!(f, "server sent an invalid response: {bytes:?}")
+            }
+            Self::Timeout => write!(f, "maximum number of reads reached"),
+            Self::LineTooLong => write!(f, "maximum buffer size reached"),
+            Self::NonAscii(bytes) => {
+                let bytes = String::from_utf8_lossy(bytes);
+                write!(f, "server sent non-ASCII bytes: {bytes:?}")
+            }
+            Self::InvalidConfig(reason) => write!(f, "invalid configuration: {reason}"),
+            Self::TrailingBytes(bytes) => {
+                let bytes = String::from_utf8_lossy(bytes);
+                write!(f, "server sent unexpected bytes after STARTTLS: {bytes:?}")
+            }
+            Self::ServerClosing(line) => write!(f, "server is closing the connection: {line}"),
+            Self::UnexpectedEof => write!(f, "server closed the connection unexpectedly"),
+            Self::WriteZero => write!(f, "failed to write the whole command"),
+            Self::AlreadySecure(line) => write!(f, "stream is already secure: {line}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UpgradeTlsError {}
+
+/// Converts the error into an [`std::io::Error`] of the closest
+/// kind, like [`std::io::ErrorKind::ConnectionRefused`] for
+/// rejections, so that it can be propagated with `?` from functions
+/// returning [`std::io::Result`]. The original error remains
+/// available via [`std::io::Error::into_inner`].
+#[cfg(feature = "std")]
+impl From<UpgradeTlsError> for std::io::Error {
+    fn from(err: UpgradeTlsError) -> Self {
+        use std::io::ErrorKind;
+
+        let kind = match &err {
+            UpgradeTlsError::Rejected { .. } => ErrorKind::ConnectionRefused,
+            UpgradeTlsError::Timeout => ErrorKind::TimedOut,
+            UpgradeTlsError::UnexpectedEof => ErrorKind::UnexpectedEof,
+            UpgradeTlsError::WriteZero => ErrorKind::WriteZero,
+            UpgradeTlsError::ServerClosing(_) => ErrorKind::ConnectionAborted,
+            UpgradeTlsError::StartTlsNotAdvertised => ErrorKind::Unsupported,
+            UpgradeTlsError::InvalidConfig(_) | UpgradeTlsError::AlreadySecure(_) => {
+                ErrorKind::InvalidInput
+            }
+            UpgradeTlsError::InvalidResponse(_)
+            | UpgradeTlsError::LineTooLong
+            | UpgradeTlsError::NonAscii(_)
+            | UpgradeTlsError::TrailingBytes(_) => ErrorKind::InvalidData,
+        };
+
+        Self::new(kind, err)
+    }
+}
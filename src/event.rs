@@ -0,0 +1,66 @@
+//! Module dedicated to the [`Event`] type.
+
+#[cfg(feature = "std")]
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+
+/// The event emitted by the [`Upgrade`](crate::Upgrade) coroutine at
+/// each state transition.
+///
+/// See [`Upgrade::set_observer`](crate::Upgrade::set_observer).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The greeting has been read and discarded.
+    GreetingDiscarded {
+        /// The number of bytes of the greeting.
+        bytes: usize,
+    },
+    /// A command has been written.
+    CommandWritten {
+        /// Whether the command was a prelude command, or the STARTTLS
+        /// one.
+        prelude: bool,
+    },
+    /// The response of a command has been received.
+    ResponseReceived {
+        /// Whether the response was the one of a prelude command, or
+        /// of the STARTTLS one.
+        prelude: bool,
+        /// Whether the server accepted the command.
+        accepted: bool,
+    },
+}
+
+/// The observer notified of [`Event`]s.
+///
+/// The observer is shared between clones, so that all coroutines
+/// built from the same prototype feed the same metrics.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub(crate) struct Observer(Arc<Mutex<dyn FnMut(Event) + Send>>);
+
+#[cfg(feature = "std")]
+impl Observer {
+    /// Creates a new observer from the given function.
+    pub(crate) fn new(f: impl FnMut(Event) + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(f)))
+    }
+
+    /// Notifies the observer of the given event.
+    pub(crate) fn notify(&self, event: Event) {
+        let mut f = match self.0.lock() {
+            Ok(f) => f,
+            Err(err) => err.into_inner(),
+        };
+
+        f(event)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
@@ -0,0 +1,76 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the FTP
+//! protocol.
+
+use memchr::memchr;
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{upgrade::reply_result, Completion, StartTls};
+
+/// The default port of the plain FTP service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 21;
+
+/// The STARTTLS command written to the server.
+pub const STARTTLS_COMMAND: &[u8] = b"AUTH TLS\r\n";
+
+/// The AUTH TLS coroutine that upgrades a plain FTP (TCP) stream to
+/// a secure one, using the explicit FTPS mode.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Ftp>;
+
+/// The FTP flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug, Default)]
+pub struct Ftp;
+
+impl StartTls for Ftp {
+    fn command(&self) -> &[u8] {
+        STARTTLS_COMMAND
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        find_reply_end(bytes).map(|n| n + 1)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let n = find_reply_end(bytes)?;
+        Some(Completion::new(n + 1, reply_result(&bytes[..=n], b"234")))
+    }
+
+    /// Prelude commands succeed with any positive completion reply
+    /// (`2xx`), like `211` for `FEAT`.
+    fn is_prelude_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let n = find_reply_end(bytes)?;
+        Some(Completion::new(n + 1, reply_result(&bytes[..=n], b"2")))
+    }
+}
+
+/// Finds the end of a (possibly multiline) FTP reply.
+///
+/// A multiline reply starts with a 3-digit code followed by a hyphen
+/// (`220-`), and ends with a line starting with the same code
+/// followed by a space (`220 `). Lines in between can be arbitrary. A
+/// single line reply is a code followed by a space. Returns the
+/// index of the `\n` terminating the last line.
+fn find_reply_end(bytes: &[u8]) -> Option<usize> {
+    let n = memchr(b'\n', bytes)?;
+
+    if bytes.get(3) != Some(&b'-') {
+        return Some(n);
+    }
+
+    let code = &bytes[..3];
+    let mut start = n + 1;
+
+    while let Some(n) = memchr(b'\n', &bytes[start..]) {
+        let end = start + n;
+        let line = &bytes[start..end];
+
+        if line.starts_with(code) && line.get(3) == Some(&b' ') {
+            return Some(end);
+        }
+
+        start = end + 1;
+    }
+
+    None
+}
@@ -1,119 +1,1143 @@
 //! Module dedicated to the [`UpgradeTls`] coroutine for the IMAP
 //! protocol.
 
-use log::debug;
-use memchr::{memchr, memmem};
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+#[cfg(feature = "std")]
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
 
+#[cfg(feature = "io-stream")]
 use io_stream::{
     coroutines::{Read, Write},
     Io,
 };
+use memchr::{memchr, memmem, memrchr};
 
-/// Internal state of the [`UpgradeTls`] flow.
-#[derive(Debug)]
-enum State {
-    /// The greeting needs to be discarded.
-    DiscardGreeting(Read),
-    /// The STARTTLS command needs to be written.
-    WriteStartTlsCommand(Write),
-    /// The STARTTLS response needs to be discarded.
-    DiscardResponse(Read),
-}
+#[cfg(feature = "io-stream")]
+use crate::{
+    upgrade::{fill, flush},
+    Upgrade,
+};
+use crate::{Completion, LineEnding, StartTls, UpgradeTlsError, DEFAULT_MAX_BUFFER};
+
+/// The default port of the plain IMAP service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 143;
+
+/// The default tag of the STARTTLS command, see
+/// [`Upgrade::set_tag`].
+pub const DEFAULT_TAG: &str = "NGC6543";
+
+/// The STARTTLS command written to the server, with the default tag.
+pub const STARTTLS_COMMAND: &[u8] = b"NGC6543 STARTTLS\r\n";
 
 /// The STARTTLS coroutine that upgrades a plain IMAP (TCP) stream to
 /// a secure one.
-#[derive(Debug)]
-pub struct UpgradeTls {
-    state: State,
-    bytes: Vec<u8>,
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Imap>;
+
+/// Upgrades the given plain IMAP stream to a secure one.
+///
+/// This is a one-shot alternative to [`UpgradeTls`], which discards
+/// the greeting then runs the coroutine to completion using the
+/// standard runtime of [`io_stream`].
+#[cfg(feature = "std")]
+pub fn upgrade_blocking(stream: &mut std::net::TcpStream) -> std::io::Result<()> {
+    UpgradeTls::new()
+        .with_discard_greeting(true)
+        .run_blocking(stream)
 }
 
-impl UpgradeTls {
-    /// The STARTTLS IMAP command.
-    // TODO: make this customizable?
-    const COMMAND: &'static str = "NGC6543 STARTTLS\r\n";
+/// Upgrades the given plain IMAP stream to a secure one.
+///
+/// This is a generic alternative to [`upgrade_blocking`], accepting
+/// any stream implementing the standard I/O traits, like Unix domain
+/// sockets or custom transports.
+#[cfg(feature = "std")]
+pub fn upgrade_blocking_io<S: std::io::Read + std::io::Write>(
+    stream: &mut S,
+) -> std::io::Result<()> {
+    UpgradeTls::new()
+        .with_discard_greeting(true)
+        .run_blocking(stream)
+}
+
+/// Upgrades the given plain IMAP stream to a secure one, using
+/// [`rustls`].
+///
+/// This is a one-shot alternative to [`UpgradeTls`], which
+/// discards the greeting, runs the coroutine to completion then
+/// performs the TLS handshake.
+#[cfg(feature = "rustls")]
+pub fn upgrade_rustls(
+    tcp: std::net::TcpStream,
+    server_name: rustls::pki_types::ServerName<'static>,
+    config: std::sync::Arc<rustls::ClientConfig>,
+) -> std::io::Result<rustls::StreamOwned<rustls::ClientConnection, std::net::TcpStream>> {
+    UpgradeTls::new()
+        .with_discard_greeting(true)
+        .run_rustls(tcp, server_name, config)
+}
 
-    /// Creates a new STARTTLS coroutine with sane defaults.
-    pub fn new() -> Self {
-        let state = State::WriteStartTlsCommand(Write::default());
-        let bytes = Vec::new();
-        Self { state, bytes }
+/// Upgrades the given plain IMAP stream to a secure one, using
+/// [`openssl`].
+///
+/// This is a one-shot alternative to [`UpgradeTls`], which
+/// discards the greeting, runs the coroutine to completion then
+/// performs the TLS handshake.
+#[cfg(feature = "openssl")]
+pub fn upgrade_openssl(
+    tcp: std::net::TcpStream,
+    domain: &str,
+    connector: &openssl::ssl::SslConnector,
+) -> std::io::Result<openssl::ssl::SslStream<std::net::TcpStream>> {
+    UpgradeTls::new()
+        .with_discard_greeting(true)
+        .run_openssl(tcp, domain, connector)
+}
+
+/// Upgrades the given plain IMAP stream to a secure one.
+///
+/// This is a one-shot async alternative to [`UpgradeTls`], which
+/// discards the greeting then runs the coroutine to completion using
+/// the tokio runtime of [`io_stream`].
+#[cfg(feature = "tokio")]
+pub async fn upgrade_tokio(stream: &mut tokio::net::TcpStream) -> std::io::Result<()> {
+    UpgradeTls::new()
+        .with_discard_greeting(true)
+        .run_tokio(stream)
+        .await
+}
+
+/// Upgrades the given plain IMAP stream to a secure one, within the
+/// given duration.
+///
+/// This is a deadline-aware alternative to [`upgrade_tokio`], which
+/// returns a [`std::io::ErrorKind::TimedOut`] error if the STARTTLS
+/// phase does not complete in time.
+#[cfg(feature = "tokio")]
+pub async fn upgrade_tokio_timeout(
+    stream: &mut tokio::net::TcpStream,
+    duration: core::time::Duration,
+) -> std::io::Result<()> {
+    UpgradeTls::new()
+        .with_discard_greeting(true)
+        .run_tokio_timeout(stream, duration)
+        .await
+}
+
+/// Upgrades the given plain IMAP stream to a secure one.
+///
+/// This is a one-shot async alternative to [`UpgradeTls`], which
+/// discards the greeting then runs the coroutine to completion using
+/// the async-std glue of [`Upgrade::run_async_std`].
+#[cfg(feature = "async-std")]
+pub async fn upgrade_async_std(stream: &mut async_std::net::TcpStream) -> std::io::Result<()> {
+    UpgradeTls::new()
+        .with_discard_greeting(true)
+        .run_async_std(stream)
+        .await
+}
+
+/// Upgrades the given plain IMAP stream to a secure one, using
+/// [`tokio_native_tls`].
+///
+/// This is a one-shot async alternative to [`UpgradeTls`], which
+/// discards the greeting, runs the coroutine to completion then
+/// performs the TLS handshake.
+#[cfg(feature = "tokio-native-tls")]
+pub async fn upgrade_native_tls(
+    tcp: tokio::net::TcpStream,
+    host: &str,
+    connector: &tokio_native_tls::TlsConnector,
+) -> std::io::Result<tokio_native_tls::TlsStream<tokio::net::TcpStream>> {
+    UpgradeTls::new()
+        .with_discard_greeting(true)
+        .run_native_tls(tcp, host, connector)
+        .await
+}
+
+/// The generator of fresh IMAP tags.
+///
+/// The generator is shared between clones, so that they all follow
+/// the same numbering scheme.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+struct TagFn(Arc<Mutex<dyn FnMut() -> String + Send>>);
+
+#[cfg(feature = "std")]
+impl TagFn {
+    /// Generates a fresh tag.
+    fn generate(&self) -> String {
+        let mut f = match self.0.lock() {
+            Ok(f) => f,
+            Err(err) => err.into_inner(),
+        };
+
+        f()
     }
+}
 
-    /// Tells the coroutine how to handle the greeting.
+#[cfg(feature = "std")]
+impl fmt::Debug for TagFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TagFn")
+    }
+}
+
+/// A response code of the STARTTLS response, as defined in [RFC
+/// 3501 section 7.1] and its extensions.
+///
+/// Response codes tell clients what the server expects next, like
+/// [`ResponseCode::PrivacyRequired`] before any authentication.
+///
+/// [RFC 3501 section 7.1]: https://www.rfc-editor.org/rfc/rfc3501#section-7.1
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ResponseCode {
+    /// The `[PRIVACYREQUIRED]` code of [RFC 5530], telling that the
+    /// operation is not permitted due to a lack of privacy.
     ///
-    /// By default, the coroutine reads and discards the greeting from
-    /// the plain stream. This setter may be useful if greeting has
-    /// already been read before: in this case, the coroutine will
-    /// directly write the STARTTLS command.
+    /// [RFC 5530]: https://www.rfc-editor.org/rfc/rfc5530
+    PrivacyRequired,
+    /// The `[ALERT]` code, holding the human-readable text that needs
+    /// to be shown to the user.
+    Alert(String),
+    /// The `[CAPABILITY ...]` code, holding the advertised
+    /// capabilities.
+    Capability(Vec<String>),
+    /// Any other code, holding its whole content like `UIDVALIDITY
+    /// 3857529045`.
+    Other(String),
+}
+
+/// The IMAP flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug)]
+pub struct Imap {
+    tag: String,
+    #[cfg(feature = "std")]
+    tag_fn: Option<TagFn>,
+    command: Vec<u8>,
+    line_ending: LineEnding,
+    check_capability: bool,
+    capabilities: Vec<String>,
+    capability_requested: bool,
+    response_codes: Vec<ResponseCode>,
+    scan_offset: usize,
+    response_marker: Option<Vec<u8>>,
+    untagged_response: bool,
+    tls_required: Option<bool>,
+    retried: usize,
+    max_buffer: usize,
+    quiet: bool,
+}
+
+impl Imap {
+    /// Builds the STARTTLS command for the given tag.
+    fn command(tag: &str) -> Vec<u8> {
+        format!("{tag} STARTTLS\r\n").into_bytes()
+    }
+
+    /// Generates a fresh tag, if a tag generator has been set.
     ///
-    /// See also [`UpgradeTls::with_discard_greeting`] for the builder
-    /// alternative.
-    pub fn discard_greeting(&mut self, discard: bool) {
-        self.state = if discard {
-            State::DiscardGreeting(Read::default())
+    /// Fails if the tag is not valid, see [`validate_tag`].
+    fn next_tag(&mut self) -> Result<(), UpgradeTlsError> {
+        #[cfg(feature = "std")]
+        if let Some(tag_fn) = &self.tag_fn {
+            self.tag = tag_fn.generate();
+            self.command = Self::command(&self.tag);
+        }
+
+        validate_tag(&self.tag)
+    }
+
+    /// Returns the tag without the suffix appended by
+    /// [`StartTls::retry`], if any.
+    fn base_tag(&self) -> &str {
+        match self.retried {
+            0 => &self.tag,
+            _ => self
+                .tag
+                .rsplit_once('.')
+                .map_or(&self.tag, |(base, _)| base),
+        }
+    }
+
+    /// Returns `true` if the `STARTTLS` capability has been
+    /// advertised by the server.
+    fn has_starttls_capability(&self) -> bool {
+        self.capabilities
+            .iter()
+            .any(|capability| capability.eq_ignore_ascii_case("STARTTLS"))
+    }
+
+    /// Records what the given line tells about TLS being required.
+    ///
+    /// The `[PRIVACYREQUIRED]` response code (RFC 5530) tells that
+    /// TLS is mandatory, while capabilities advertised before
+    /// STARTTLS tell it through the presence of `LOGINDISABLED`.
+    fn observe_tls_required(&mut self, line: &[u8]) {
+        let required = if let Some(ResponseCode::PrivacyRequired) = parse_response_code(line) {
+            true
+        } else if let Some(capabilities) =
+            find_capability_code(line).or_else(|| line.strip_prefix(b"* CAPABILITY "))
+        {
+            has_login_disabled(&parse_capabilities(capabilities))
+        } else {
+            return;
+        };
+
+        self.tls_required = Some(required || self.tls_required == Some(true));
+    }
+
+    /// Returns the prefix of the line completing the STARTTLS
+    /// response.
+    fn response_marker(&self) -> Vec<u8> {
+        match &self.response_marker {
+            Some(marker) => marker.clone(),
+            None => format!("{} ", self.tag).into_bytes(),
+        }
+    }
+
+    /// Finds the first line starting with the tag.
+    ///
+    /// Lines are processed one by one, so the tag is never matched
+    /// inside another line. Untagged lines received in between are
+    /// discarded, except `BYE` which is returned instead since the
+    /// server closes the connection right after. Literals announced
+    /// by untagged lines are skipped as a whole, so that their data is
+    /// never mistaken for the tagged line. Returns the index of the
+    /// first byte of the tagged line alongside the index of its
+    /// terminating `\n`, or an error if a literal length announced
+    /// by the server could never fit in the buffer.
+    fn find_tagged_line(
+        &mut self,
+        bytes: &[u8],
+    ) -> Option<Result<(usize, usize), UpgradeTlsError>> {
+        let marker = self.response_marker();
+        let mut start = self.scan_start(bytes);
+
+        while let Some(n) = self.line_ending.find(&bytes[start..]) {
+            let end = start + n;
+            let line = &bytes[start..=end];
+
+            if line.starts_with(&marker) || line.starts_with(BYE) {
+                self.scan_offset = 0;
+                return Some(Ok((start, end)));
+            }
+
+            // some buggy servers do not echo the tag back, in which
+            // case the first line which is not untagged data is
+            // considered as the response
+            if self.untagged_response && !line.starts_with(b"* ") {
+                self.scan_offset = 0;
+                return Some(Ok((start, end)));
+            }
+
+            if !self.quiet {
+                debug!("discard untagged line {:?}", String::from_utf8_lossy(line));
+            }
+
+            // the untagged line goes on after its literals, which
+            // may contain CRLFs or even the tag
+            let mut end = end;
+            let mut segment = line;
+
+            while let Some(len) = literal_len(segment) {
+                // the length comes from the server, so it may
+                // overflow or exceed the buffer on purpose
+                let Some(literal_end) = (end + 1).checked_add(len) else {
+                    self.scan_offset = 0;
+                    let line = segment.to_vec();
+                    return Some(Err(UpgradeTlsError::InvalidResponse(line)));
+                };
+
+                if len > self.max_buffer {
+                    self.scan_offset = 0;
+                    return Some(Err(UpgradeTlsError::LineTooLong));
+                }
+
+                let n = self.line_ending.find(bytes.get(literal_end..)?)?;
+                segment = &bytes[literal_end..=literal_end + n];
+                end = literal_end + n;
+            }
+
+            start = end + 1;
+            self.scan_offset = start;
+        }
+
+        None
+    }
+
+    /// Returns the index of the first line not scanned yet.
+    ///
+    /// Bytes are only appended to the buffer until the current step
+    /// completes, so complete lines scanned by a previous call do not
+    /// need to be scanned again. This bounds the work of every call
+    /// for servers sending verbose untagged data.
+    fn scan_start(&self, bytes: &[u8]) -> usize {
+        self.scan_offset.min(bytes.len())
+    }
+
+    /// Builds the result of the given tagged line.
+    ///
+    /// The status is the token following the tag (or the response
+    /// marker).
+    fn tagged_result(&self, line: &[u8]) -> Result<(), UpgradeTlsError> {
+        let response = memchr(b' ', line).map_or(&line[..0], |n| &line[n + 1..]);
+        status_result(response, line)
+    }
+}
+
+impl Default for Imap {
+    fn default() -> Self {
+        Self {
+            tag: DEFAULT_TAG.to_owned(),
+            #[cfg(feature = "std")]
+            tag_fn: None,
+            command: Self::command(DEFAULT_TAG),
+            line_ending: LineEnding::default(),
+            check_capability: false,
+            capabilities: Vec::new(),
+            capability_requested: false,
+            response_codes: Vec::new(),
+            scan_offset: 0,
+            response_marker: None,
+            untagged_response: false,
+            tls_required: None,
+            retried: 0,
+            max_buffer: DEFAULT_MAX_BUFFER,
+            quiet: false,
+        }
+    }
+}
+
+impl StartTls for Imap {
+    fn command(&self) -> &[u8] {
+        &self.command
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        let n = self.line_ending.find(bytes)?;
+        self.observe_tls_required(&bytes[..=n]);
+
+        if self.check_capability {
+            if let Some(capabilities) = find_capability_code(&bytes[..=n]) {
+                self.capabilities = parse_capabilities(capabilities);
+            }
+        }
+
+        Some(n + 1)
+    }
+
+    fn prelude(&mut self) -> Result<Option<Vec<u8>>, UpgradeTlsError> {
+        // every command gets its own tag, so a fresh one is generated
+        // before writing the CAPABILITY or the STARTTLS command
+        if self.check_capability {
+            if self.capabilities.is_empty() && !self.capability_requested {
+                self.capability_requested = true;
+                self.next_tag()?;
+                let command = format!("{} CAPABILITY\r\n", self.tag);
+                return Ok(Some(command.into_bytes()));
+            }
+
+            if !self.has_starttls_capability() {
+                return Err(UpgradeTlsError::StartTlsNotAdvertised);
+            }
+        }
+
+        self.next_tag()?;
+        Ok(None)
+    }
+
+    fn validate(&self) -> Result<(), UpgradeTlsError> {
+        validate_tag(&self.tag)
+    }
+
+    fn has_prelude(&self) -> bool {
+        self.check_capability
+    }
+
+    fn is_prelude_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let mut start = self.scan_start(bytes);
+
+        // prelude commands may have been queued with their own tag,
+        // so the first line being neither untagged nor a continuation
+        // request is considered as the tagged response
+        while let Some(n) = self.line_ending.find(&bytes[start..]) {
+            let end = start + n;
+            let line = &bytes[start..=end];
+
+            if line.starts_with(BYE) {
+                self.scan_offset = 0;
+                return Some(Completion::new(end + 1, Err(closing(line))));
+            }
+
+            self.observe_tls_required(line);
+
+            if let Some(capabilities) = line.strip_prefix(b"* CAPABILITY ") {
+                self.capabilities = parse_capabilities(capabilities);
+            } else if !line.starts_with(b"* ") && !line.starts_with(b"+ ") {
+                self.scan_offset = 0;
+                return Some(Completion::new(end + 1, self.tagged_result(line)));
+            }
+
+            start = end + 1;
+            self.scan_offset = start;
+        }
+
+        None
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let (start, end) = match self.find_tagged_line(bytes)? {
+            Ok(line) => line,
+            Err(err) => return Some(Completion::new(bytes.len(), Err(err))),
+        };
+        let line = &bytes[start..=end];
+
+        if line.starts_with(BYE) {
+            return Some(Completion::new(end + 1, Err(closing(line))));
+        }
+
+        // capabilities of the response are the ones available once
+        // upgraded, so only the response code is relevant here
+        if let Some(ResponseCode::PrivacyRequired) = parse_response_code(line) {
+            self.tls_required = Some(true);
+        }
+
+        let result = if self.untagged_response && !line.starts_with(&self.response_marker()) {
+            status_result(line, line)
         } else {
-            State::WriteStartTlsCommand(Write::default())
+            self.tagged_result(line)
         };
+
+        if result.is_ok() {
+            self.response_codes.extend(parse_response_code(line));
+
+            // the response may advertise the capabilities available
+            // once upgraded, which saves a CAPABILITY round trip
+            if let Some(capabilities) = find_capability_code(line) {
+                self.capabilities = parse_capabilities(capabilities);
+            }
+        }
+
+        Some(Completion::new(end + 1, result))
+    }
+
+    /// The status is the token following the tag (or the response
+    /// marker), like `OK` in `NGC6543 OK begin TLS`.
+    fn status<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        let response = memchr(b' ', line).map_or(&line[..0], |n| &line[n + 1..]);
+        parse_status(response)
+    }
+
+    fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    fn set_max_buffer(&mut self, max: usize) {
+        self.max_buffer = max;
+    }
+
+    /// Lines before the scan offset have been fully processed, along
+    /// with their literals, so they are never needed again.
+    fn drain_offset(&self, bytes: &[u8]) -> Option<usize> {
+        Some(self.scan_start(bytes)).filter(|n| *n > 0)
+    }
+
+    fn drained(&mut self, n: usize) {
+        self.scan_offset = self.scan_offset.saturating_sub(n);
+    }
+
+    fn reset(&mut self) {
+        self.capabilities.clear();
+        self.capability_requested = false;
+        self.response_codes.clear();
+        self.scan_offset = 0;
+        self.tls_required = None;
+        self.tag = self.base_tag().to_owned();
+        self.command = Self::command(&self.tag);
+        self.retried = 0;
+    }
+
+    /// The command is written again with a fresh tag, since a tag
+    /// must not be reused. Without tag generator, the attempt number
+    /// is appended to the configured tag, like `NGC6543.1`.
+    fn retry(&mut self) {
+        // the generator gives a fresh tag when rebuilding the command
+        #[cfg(feature = "std")]
+        if self.tag_fn.is_some() {
+            return;
+        }
+
+        let base = self.base_tag().to_owned();
+        self.retried += 1;
+        self.tag = format!("{base}.{}", self.retried);
+        self.command = Self::command(&self.tag);
+    }
+
+    fn tag(&self) -> Option<&str> {
+        Some(&self.tag)
+    }
+
+    fn tls_required(&self) -> Option<bool> {
+        self.tls_required
+    }
+
+    fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+}
+
+#[cfg(feature = "io-stream")]
+impl Upgrade<Imap> {
+    /// Sets the line ending used to detect the end of lines.
+    ///
+    /// Defaults to [`LineEnding::Lf`], which is the most lenient
+    /// choice. [`LineEnding::CrLf`] may be useful to strictly follow
+    /// the IMAP specification.
+    ///
+    /// See also [`UpgradeTls::with_line_ending`] for the builder
+    /// alternative.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.protocol_mut().line_ending = line_ending;
     }
 
-    /// Builder alternative to [`UpgradeTls::discard_greeting`].
-    pub fn with_discard_greeting(mut self, discard: bool) -> Self {
-        self.discard_greeting(discard);
+    /// Builder alternative to [`UpgradeTls::set_line_ending`].
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.set_line_ending(line_ending);
         self
     }
 
-    /// Makes the coroutine progress.
-    pub fn resume(&mut self, mut io: Option<Io>) -> Result<(), Io> {
+    /// Sets the tag of the STARTTLS command.
+    ///
+    /// Defaults to `NGC6543`. The tag is used both to build the
+    /// command sent to the server and to find the tagged response
+    /// line. This setter may be useful if the default tag collides
+    /// with tags of other commands. Replaces any tag generator set via
+    /// `UpgradeTls::set_tag_fn`. An empty tag, or a tag containing
+    /// spaces or line terminators, makes the coroutine (and
+    /// [`Upgrade::starting_state`]) fail with
+    /// [`UpgradeTlsError::InvalidConfig`] before writing anything.
+    ///
+    /// See also [`UpgradeTls::with_tag`] for the builder alternative.
+    pub fn set_tag(&mut self, tag: impl Into<String>) {
+        let imap = self.protocol_mut();
+        imap.tag = tag.into();
+        imap.retried = 0;
+        #[cfg(feature = "std")]
+        {
+            imap.tag_fn = None;
+        }
+        imap.command = Imap::command(&imap.tag);
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_tag`].
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.set_tag(tag);
+        self
+    }
+
+    /// Sets the generator of the tags used by the coroutine commands.
+    ///
+    /// The generator is called once per command, right before writing
+    /// it. This allows the coroutine to follow the numbering scheme
+    /// of clients tracking their own tag counters (like `A001`,
+    /// `A002`…). The generator is shared between clones of the
+    /// coroutine. Replaces any static tag set via
+    /// [`UpgradeTls::set_tag`].
+    ///
+    /// See also [`UpgradeTls::with_tag_fn`] for the builder
+    /// alternative.
+    #[cfg(feature = "std")]
+    pub fn set_tag_fn(&mut self, f: impl FnMut() -> String + Send + 'static) {
+        self.protocol_mut().tag_fn = Some(TagFn(Arc::new(Mutex::new(f))));
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_tag_fn`].
+    #[cfg(feature = "std")]
+    pub fn with_tag_fn(mut self, f: impl FnMut() -> String + Send + 'static) -> Self {
+        self.set_tag_fn(f);
+        self
+    }
+
+    /// Sets the prefix of the line completing the STARTTLS response.
+    ///
+    /// Defaults to the tag followed by a space. This setter may be
+    /// useful when the STARTTLS command has been overridden via
+    /// [`Upgrade::set_command`], since the coroutine cannot guess
+    /// the tag of a custom command anymore.
+    ///
+    /// See also [`UpgradeTls::with_response_marker`] for the builder
+    /// alternative.
+    pub fn set_response_marker(&mut self, marker: impl Into<Vec<u8>>) {
+        self.protocol_mut().response_marker = Some(marker.into());
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_response_marker`].
+    pub fn with_response_marker(mut self, marker: impl Into<Vec<u8>>) -> Self {
+        self.set_response_marker(marker);
+        self
+    }
+
+    /// Tells the coroutine to accept a STARTTLS response without tag.
+    ///
+    /// Some buggy servers answer the STARTTLS command with a bare
+    /// `OK\r\n`, without echoing the tag back, which makes the
+    /// coroutine wait forever (or until the maximum number of reads is
+    /// reached, see [`Upgrade::set_max_reads`]). When enabled, the
+    /// first line which is not untagged data (`* ...`) is considered
+    /// as the response, whether it is tagged or not.
+    ///
+    /// This mode is not conformant with the IMAP specification, and
+    /// should only be enabled to interoperate with such servers.
+    ///
+    /// See also [`UpgradeTls::with_untagged_response`] for the
+    /// builder alternative.
+    pub fn set_untagged_response(&mut self, untagged: bool) {
+        self.protocol_mut().untagged_response = untagged;
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_untagged_response`].
+    pub fn with_untagged_response(mut self, untagged: bool) -> Self {
+        self.set_untagged_response(untagged);
+        self
+    }
+
+    /// Tells the coroutine to check the server capabilities before
+    /// sending the STARTTLS command.
+    ///
+    /// By default, the STARTTLS command is sent without checking
+    /// capabilities. When enabled, capabilities are taken from the
+    /// `[CAPABILITY ...]` response code of the greeting if any,
+    /// otherwise from the response of a CAPABILITY command. If the
+    /// `STARTTLS` capability is not advertised, the coroutine fails
+    /// with [`UpgradeTlsError::StartTlsNotAdvertised`].
+    ///
+    /// See also [`UpgradeTls::with_check_capability`] for the builder
+    /// alternative.
+    pub fn check_capability(&mut self, check: bool) {
+        self.protocol_mut().check_capability = check;
+    }
+
+    /// Builder alternative to [`UpgradeTls::check_capability`].
+    pub fn with_check_capability(mut self, check: bool) -> Self {
+        self.check_capability(check);
+        self
+    }
+
+    /// Returns the capabilities advertised by the server.
+    ///
+    /// Capabilities are collected before STARTTLS when
+    /// [`UpgradeTls::check_capability`] is enabled. They are replaced
+    /// by the ones of the `[CAPABILITY ...]` response code of the
+    /// STARTTLS response, if the server accepted the command and sent
+    /// such code. This slice is empty otherwise.
+    pub fn capabilities(&self) -> &[String] {
+        &self.protocol().capabilities
+    }
+
+    /// Returns the response codes of the STARTTLS response.
+    ///
+    /// Codes are parsed from the response line once the server
+    /// accepted the command, like `[PRIVACYREQUIRED]` in `NGC6543 OK
+    /// [PRIVACYREQUIRED] begin TLS`. This slice is empty otherwise.
+    pub fn response_codes(&self) -> &[ResponseCode] {
+        &self.protocol().response_codes
+    }
+}
+
+/// Creates a new CAPABILITY coroutine using the `A` tag.
+///
+/// See [`Capability`].
+#[cfg(feature = "io-stream")]
+pub fn capability() -> Capability {
+    Capability::new("A")
+}
+
+/// Internal state of the [`Capability`] and [`Noop`] flows.
+#[cfg(feature = "io-stream")]
+#[derive(Clone, Debug)]
+enum CommandState {
+    /// The tag is not valid, the flow fails without writing anything.
+    Invalid(UpgradeTlsError),
+    /// The command needs to be written.
+    WriteCommand(Write),
+    /// The command response needs to be read.
+    ReadResponse,
+}
+
+/// The tagged command flow shared by the [`Capability`] and [`Noop`]
+/// coroutines.
+#[cfg(feature = "io-stream")]
+#[derive(Clone, Debug)]
+struct TaggedCommand {
+    tag: String,
+    state: CommandState,
+    read: Read,
+    bytes: Vec<u8>,
+}
+
+#[cfg(feature = "io-stream")]
+impl TaggedCommand {
+    /// Creates a new flow writing the given command with the given
+    /// tag.
+    ///
+    /// The tag is checked like the one of the STARTTLS command: an
+    /// invalid tag makes the flow fail on its first resume.
+    fn new(tag: String, name: &str) -> Self {
+        let state = match validate_tag(&tag) {
+            Ok(()) => {
+                let command = format!("{tag} {name}\r\n");
+                debug!("enqueue command {command:?}");
+                CommandState::WriteCommand(Write::new(command.into_bytes()))
+            }
+            Err(err) => CommandState::Invalid(err),
+        };
+
+        Self {
+            tag,
+            state,
+            read: Read::default(),
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Makes the flow progress, until the given parser finds the
+    /// tagged response within the bytes received so far.
+    fn resume<T>(
+        &mut self,
+        mut io: Option<Io>,
+        parse: impl Fn(&str, &[u8]) -> Option<Result<T, UpgradeTlsError>>,
+    ) -> Result<Result<T, UpgradeTlsError>, Io> {
         loop {
             match &mut self.state {
-                State::DiscardGreeting(read) => {
-                    let output = read.resume(io.take())?;
-                    self.bytes.extend(output.bytes());
-
-                    match memchr(b'\n', &self.bytes) {
-                        Some(n) => {
-                            let bytes = String::from_utf8_lossy(&self.bytes[..=n]);
-                            debug!("discard greeting line {bytes:?}");
-                        }
-                        None => {
-                            read.replace(output.buffer);
-                            continue;
-                        }
-                    };
-
-                    let bytes = Self::COMMAND.as_bytes().to_vec();
-                    self.state = State::WriteStartTlsCommand(Write::new(bytes));
-                    debug!("enqueue command {:?}", Self::COMMAND);
-                }
-                State::WriteStartTlsCommand(write) => {
-                    write.resume(io.take())?;
-                    self.bytes.clear();
-                    self.state = State::DiscardResponse(Read::default());
-                }
-                State::DiscardResponse(read) => {
-                    let output = read.resume(io.take())?;
-                    self.bytes.extend(output.bytes());
-
-                    // no response line found, keep reading
-                    let Some(n) = memmem::find(&self.bytes, b"NGC6543 ") else {
-                        read.replace(output.buffer);
-                        continue;
-                    };
-
-                    match memchr(b'\n', &self.bytes[n..]) {
-                        Some(m) => {
-                            let bytes = String::from_utf8_lossy(&self.bytes[n..=m]);
-                            debug!("discard line {bytes:?}");
-                            break Ok(());
-                        }
-                        None => {
-                            read.replace(output.buffer);
-                            continue;
-                        }
-                    };
+                CommandState::Invalid(err) => break Ok(Err(err.clone())),
+                CommandState::WriteCommand(write) => match flush(write, io.take())? {
+                    Ok(true) => self.state = CommandState::ReadResponse,
+                    Ok(false) => continue,
+                    Err(err) => break Ok(Err(err)),
+                },
+                CommandState::ReadResponse => {
+                    if let Some(result) = parse(&self.tag, &self.bytes) {
+                        break Ok(result);
+                    }
+
+                    if let Err(err) = fill(&mut self.read, &mut self.bytes, io.take())? {
+                        break Ok(Err(err));
+                    }
                 }
             }
         }
     }
 }
+
+/// The tagged line of a response.
+#[cfg(feature = "io-stream")]
+struct TaggedLine<'a> {
+    /// The whole line, line ending included.
+    line: &'a [u8],
+    /// The part of the line following the tag.
+    response: &'a [u8],
+}
+
+/// Finds the line starting with the given tag, within the response
+/// received so far.
+///
+/// Untagged lines received before are handed over to the given
+/// closure, except `BYE` which fails with
+/// [`UpgradeTlsError::ServerClosing`] since the server closes the
+/// connection right after.
+#[cfg(feature = "io-stream")]
+fn find_tagged_response<'a>(
+    tag: &str,
+    bytes: &'a [u8],
+    mut untagged: impl FnMut(&'a [u8]),
+) -> Option<Result<TaggedLine<'a>, UpgradeTlsError>> {
+    let marker = format!("{tag} ");
+    let mut start = 0;
+
+    while let Some(n) = memchr(b'\n', &bytes[start..]) {
+        let end = start + n;
+        let line = &bytes[start..=end];
+
+        if line.starts_with(BYE) {
+            return Some(Err(closing(line)));
+        }
+
+        if let Some(response) = line.strip_prefix(marker.as_bytes()) {
+            debug!("receive response {:?}", String::from_utf8_lossy(line));
+            return Some(Ok(TaggedLine { line, response }));
+        }
+
+        untagged(line);
+        start = end + 1;
+    }
+
+    None
+}
+
+/// The coroutine that sends the CAPABILITY command and collects the
+/// capabilities advertised by the server.
+///
+/// Capabilities may change once the stream is upgraded, since
+/// security-related ones like `AUTH=PLAIN` are usually only
+/// advertised over TLS. This coroutine is meant to be resumed
+/// against the secure stream.
+#[cfg(feature = "io-stream")]
+#[derive(Clone, Debug)]
+pub struct Capability {
+    command: TaggedCommand,
+}
+
+#[cfg(feature = "io-stream")]
+impl Capability {
+    /// Creates a new CAPABILITY coroutine using the given tag.
+    pub fn new(tag: impl Into<String>) -> Self {
+        Self {
+            command: TaggedCommand::new(tag.into(), "CAPABILITY"),
+        }
+    }
+
+    /// Makes the coroutine progress.
+    ///
+    /// The coroutine returns `Err(io)` as long as it needs I/O to be
+    /// processed. Once the tagged response has been received, it
+    /// returns `Ok(Ok(capabilities))` if the server accepted the
+    /// command, otherwise `Ok(Err(err))`. Capabilities are collected
+    /// from the untagged `* CAPABILITY` line. The coroutine also
+    /// fails if the server closed or is closing the connection, or
+    /// if the response exceeds [`DEFAULT_MAX_BUFFER`].
+    ///
+    /// [`DEFAULT_MAX_BUFFER`]: crate::DEFAULT_MAX_BUFFER
+    pub fn resume(&mut self, io: Option<Io>) -> Result<Result<Vec<String>, UpgradeTlsError>, Io> {
+        self.command.resume(io, parse_capability_response)
+    }
+}
+
+/// Parses the CAPABILITY response received so far, if the tagged
+/// line has been received.
+#[cfg(feature = "io-stream")]
+fn parse_capability_response(
+    tag: &str,
+    bytes: &[u8],
+) -> Option<Result<Vec<String>, UpgradeTlsError>> {
+    let mut capabilities = Vec::new();
+
+    let tagged = find_tagged_response(tag, bytes, |line| {
+        if let Some(line) = line.strip_prefix(b"* CAPABILITY ") {
+            capabilities = parse_capabilities(line);
+        }
+    })?;
+
+    let result = tagged.and_then(|tagged| status_result(tagged.response, tagged.line));
+    Some(result.map(|()| capabilities))
+}
+
+/// Creates a new NOOP coroutine using the `A` tag.
+///
+/// See [`Noop`].
+#[cfg(feature = "io-stream")]
+pub fn noop() -> Noop {
+    Noop::new("A")
+}
+
+/// The status of a tagged response.
+#[cfg(feature = "io-stream")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Status {
+    /// The command completed successfully.
+    Ok,
+    /// The command failed.
+    No,
+    /// The command was not accepted, like an unknown command.
+    Bad,
+}
+
+/// The coroutine that sends the NOOP command and reads its tagged
+/// response.
+///
+/// This is meant to be resumed against the secure stream, as a probe
+/// verifying that the channel works once upgraded. The TLS handshake
+/// itself needs to be done by the caller.
+#[cfg(feature = "io-stream")]
+#[derive(Clone, Debug)]
+pub struct Noop {
+    command: TaggedCommand,
+}
+
+#[cfg(feature = "io-stream")]
+impl Noop {
+    /// Creates a new NOOP coroutine using the given tag.
+    pub fn new(tag: impl Into<String>) -> Self {
+        Self {
+            command: TaggedCommand::new(tag.into(), "NOOP"),
+        }
+    }
+
+    /// Makes the coroutine progress.
+    ///
+    /// The coroutine returns `Err(io)` as long as it needs I/O to be
+    /// processed. Once the tagged response has been received, it
+    /// returns `Ok(Ok(status))`, or `Ok(Err(err))` if the response
+    /// could not be parsed, if the server closed or is closing the
+    /// connection, or if the response exceeds
+    /// [`DEFAULT_MAX_BUFFER`].
+    ///
+    /// [`DEFAULT_MAX_BUFFER`]: crate::DEFAULT_MAX_BUFFER
+    pub fn resume(&mut self, io: Option<Io>) -> Result<Result<Status, UpgradeTlsError>, Io> {
+        self.command.resume(io, parse_noop_response)
+    }
+}
+
+/// Parses the NOOP response received so far, if the tagged line has
+/// been received.
+#[cfg(feature = "io-stream")]
+fn parse_noop_response(tag: &str, bytes: &[u8]) -> Option<Result<Status, UpgradeTlsError>> {
+    let TaggedLine { line, response } = match find_tagged_response(tag, bytes, |_| ())? {
+        Ok(tagged) => tagged,
+        Err(err) => return Some(Err(err)),
+    };
+
+    let status = parse_status(response);
+    let status = if status.eq_ignore_ascii_case(b"OK") {
+        Status::Ok
+    } else if status.eq_ignore_ascii_case(b"NO") {
+        Status::No
+    } else if status.eq_ignore_ascii_case(b"BAD") {
+        Status::Bad
+    } else {
+        return Some(Err(UpgradeTlsError::InvalidResponse(line.to_vec())));
+    };
+
+    Some(Ok(status))
+}
+
+/// Checks that the given tag can be written as is.
+///
+/// A tag containing line terminators would let another command be
+/// injected before the STARTTLS one, while an empty tag or a tag
+/// containing spaces or control characters would not be matched
+/// back in the tagged response line.
+fn validate_tag(tag: &str) -> Result<(), UpgradeTlsError> {
+    let reason = if tag.contains(['\r', '\n']) {
+        format!("IMAP tag {tag:?} contains line terminators")
+    } else if tag.is_empty()
+        || tag.contains(|c: char| c.is_ascii_whitespace() || c.is_ascii_control())
+    {
+        format!("IMAP tag {tag:?} is empty or contains spaces")
+    } else {
+        return Ok(());
+    };
+
+    Err(UpgradeTlsError::InvalidConfig(reason))
+}
+
+/// The prefix of the untagged response announcing that the server
+/// is closing the connection.
+const BYE: &[u8] = b"* BYE";
+
+/// Builds the error of the given `BYE` line.
+fn closing(line: &[u8]) -> UpgradeTlsError {
+    let line = String::from_utf8_lossy(line).trim_end().to_owned();
+    UpgradeTlsError::ServerClosing(line)
+}
+
+/// Builds the result of the given response, which succeeds if its
+/// status is `OK`, case-insensitively.
+fn status_result(response: &[u8], line: &[u8]) -> Result<(), UpgradeTlsError> {
+    let status = parse_status(response);
+
+    if status.eq_ignore_ascii_case(b"OK") {
+        return Ok(());
+    }
+
+    Err(UpgradeTlsError::Rejected {
+        status: String::from_utf8_lossy(status).into_owned(),
+        line: String::from_utf8_lossy(line).trim_end().to_owned(),
+    })
+}
+
+/// Parses the status of the given response, located right after the
+/// tag (like `OK`, `NO` or `BAD`).
+fn parse_status(response: &[u8]) -> &[u8] {
+    response
+        .split(u8::is_ascii_whitespace)
+        .next()
+        .unwrap_or_default()
+}
+
+/// Finds the content of the `[CAPABILITY ...]` response code of the
+/// given line, if any.
+fn find_capability_code(line: &[u8]) -> Option<&[u8]> {
+    const CODE: &[u8] = b"[CAPABILITY ";
+    let start = memmem::find(line, CODE)? + CODE.len();
+    let end = start + memchr(b']', &line[start..])?;
+    Some(&line[start..end])
+}
+
+/// Parses the response code of the given status response line, if
+/// any.
+///
+/// The code is expected right after the tag and the status, like in
+/// `NGC6543 OK [ALERT] text`.
+fn parse_response_code(line: &[u8]) -> Option<ResponseCode> {
+    let mut words = line.splitn(3, |byte| *byte == b' ');
+    let text = words.nth(2)?.strip_prefix(b"[")?;
+    let end = memchr(b']', text)?;
+    let code = &text[..end];
+
+    let (atom, args) = match memchr(b' ', code) {
+        Some(n) => (&code[..n], &code[n + 1..]),
+        None => (code, &code[..0]),
+    };
+
+    let code = if atom.eq_ignore_ascii_case(b"PRIVACYREQUIRED") {
+        ResponseCode::PrivacyRequired
+    } else if atom.eq_ignore_ascii_case(b"ALERT") {
+        let text = String::from_utf8_lossy(&text[end + 1..]);
+        ResponseCode::Alert(text.trim().to_owned())
+    } else if atom.eq_ignore_ascii_case(b"CAPABILITY") {
+        ResponseCode::Capability(parse_capabilities(args))
+    } else {
+        ResponseCode::Other(String::from_utf8_lossy(code).into_owned())
+    };
+
+    Some(code)
+}
+
+/// Returns the length of the literal announced at the end of the
+/// given line, if any, like `{5}` or its non-synchronizing form `{5+}`
+/// defined in [RFC 7888].
+///
+/// [RFC 7888]: https://www.rfc-editor.org/rfc/rfc7888
+fn literal_len(line: &[u8]) -> Option<usize> {
+    let line = line.strip_suffix(b"\n")?;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = line.strip_suffix(b"}")?;
+    let digits = &line[memrchr(b'{', line)? + 1..];
+    let digits = digits.strip_suffix(b"+").unwrap_or(digits);
+
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    core::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// Parses the given space-separated capabilities.
+fn parse_capabilities(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .split(u8::is_ascii_whitespace)
+        .filter(|capability| !capability.is_empty())
+        .map(|capability| String::from_utf8_lossy(capability).into_owned())
+        .collect()
+}
+
+/// Returns `true` if the given capabilities contain `LOGINDISABLED`,
+/// which tells that authenticating requires TLS.
+fn has_login_disabled(capabilities: &[String]) -> bool {
+    capabilities
+        .iter()
+        .any(|capability| capability.eq_ignore_ascii_case("LOGINDISABLED"))
+}
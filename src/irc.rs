@@ -0,0 +1,112 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the IRC
+//! protocol.
+//!
+//! STARTTLS is an IRCv3 extension: the server answers the command
+//! with either the `670` (success) or the `691` (failure) numeric.
+//!
+//! See <https://ircv3.net/specs/deprecated/tls>.
+
+use alloc::{borrow::ToOwned, string::String};
+
+use memchr::{memchr, memrchr};
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain IRC service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 6667;
+
+/// The STARTTLS command written to the server.
+pub const STARTTLS_COMMAND: &[u8] = b"STARTTLS\r\n";
+
+/// The numeric of a successful STARTTLS response.
+const RPL_STARTTLS: &[u8] = b"670";
+
+/// The numeric of a failed STARTTLS response.
+const ERR_STARTTLS: &[u8] = b"691";
+
+/// The STARTTLS coroutine that upgrades a plain IRC (TCP) stream to
+/// a secure one.
+///
+/// IRC servers do not send a proper greeting, but rather unsolicited
+/// notices. Discarding the greeting discards the lines received by
+/// the first read.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Irc>;
+
+/// The IRC flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug, Default)]
+pub struct Irc;
+
+impl StartTls for Irc {
+    fn command(&self) -> &[u8] {
+        STARTTLS_COMMAND
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        memrchr(b'\n', bytes).map(|n| n + 1)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let (start, end) = find_numeric_line(bytes)?;
+        let line = &bytes[start..=end];
+
+        if parse_command(line) == Some(RPL_STARTTLS) {
+            return Some(Completion::new(end + 1, Ok(())));
+        }
+
+        let err = UpgradeTlsError::Rejected {
+            status: String::from_utf8_lossy(ERR_STARTTLS).into_owned(),
+            line: String::from_utf8_lossy(line).trim_end().to_owned(),
+        };
+
+        Some(Completion::new(end + 1, Err(err)))
+    }
+
+    /// The status is the command of the message, located after the
+    /// optional tags and source, like `670` in `:irc.example.net 670
+    /// nick :STARTTLS successful`.
+    fn status<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        parse_command(line).unwrap_or_default()
+    }
+}
+
+/// Finds the first line carrying a STARTTLS numeric.
+///
+/// Any other line (like notices or pings) is skipped. Returns the
+/// index of the first byte of the line alongside the index of its
+/// terminating `\n`.
+fn find_numeric_line(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 0;
+
+    while let Some(n) = memchr(b'\n', &bytes[start..]) {
+        let end = start + n;
+        let command = parse_command(&bytes[start..end]);
+
+        if command == Some(RPL_STARTTLS) || command == Some(ERR_STARTTLS) {
+            return Some((start, end));
+        }
+
+        start = end + 1;
+    }
+
+    None
+}
+
+/// Parses the command (or numeric) of the given IRC message, located
+/// right after the optional tags and source.
+fn parse_command(line: &[u8]) -> Option<&[u8]> {
+    let mut tokens = line
+        .split(|b| *b == b' ')
+        .filter(|token| !token.is_empty())
+        .skip_while(|token| token.starts_with(b"@"));
+
+    let token = tokens.next()?;
+
+    if token.starts_with(b":") {
+        tokens.next()
+    } else {
+        Some(token)
+    }
+}
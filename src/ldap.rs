@@ -0,0 +1,272 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the LDAP
+//! protocol.
+//!
+//! Unlike text-based protocols, LDAP StartTLS is an extended
+//! operation encoded in BER (see [RFC 4511]). There is no greeting:
+//! the client directly sends the StartTLS extended request, then the
+//! server answers with an extended response.
+//!
+//! [RFC 4511]: https://www.rfc-editor.org/rfc/rfc4511#section-4.14
+
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain LDAP service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 389;
+
+/// BER tag of a SEQUENCE.
+const SEQUENCE: u8 = 0x30;
+/// BER tag of an INTEGER.
+const INTEGER: u8 = 0x02;
+/// BER tag of an OCTET STRING.
+const OCTET_STRING: u8 = 0x04;
+/// BER tag of an ENUMERATED.
+const ENUMERATED: u8 = 0x0a;
+/// BER tag of the ExtendedRequest protocol operation
+/// (`[APPLICATION 23]`, constructed).
+const EXTENDED_REQUEST: u8 = 0x77;
+/// BER tag of the ExtendedResponse protocol operation
+/// (`[APPLICATION 24]`, constructed).
+const EXTENDED_RESPONSE: u8 = 0x78;
+/// BER tag of the ExtendedRequest name (`[0]`, primitive).
+const REQUEST_NAME: u8 = 0x80;
+
+/// The StartTLS coroutine that upgrades a plain LDAP (TCP) stream to
+/// a secure one.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Ldap>;
+
+/// The LDAP flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug)]
+pub struct Ldap {
+    message_id: u32,
+    command: Vec<u8>,
+    quiet: bool,
+}
+
+impl Ldap {
+    /// The StartTLS extended operation OID.
+    const OID: &'static str = "1.3.6.1.4.1.1466.20037";
+
+    /// The default message ID of the StartTLS extended request.
+    const MESSAGE_ID: u32 = 1;
+
+    /// The result code of a successful extended response.
+    const SUCCESS: u32 = 0;
+
+    /// Builds the StartTLS extended request matching the given
+    /// message ID.
+    fn command(message_id: u32) -> Vec<u8> {
+        let oid = Self::OID.as_bytes();
+        let id = encode_integer(message_id);
+
+        let mut name = vec![REQUEST_NAME, oid.len() as u8];
+        name.extend(oid);
+
+        let mut op = vec![EXTENDED_REQUEST, name.len() as u8];
+        op.extend(name);
+
+        let mut content = vec![INTEGER, id.len() as u8];
+        content.extend(id);
+        content.extend(op);
+
+        let mut message = vec![SEQUENCE, content.len() as u8];
+        message.extend(content);
+        message
+    }
+}
+
+impl Default for Ldap {
+    fn default() -> Self {
+        Self {
+            message_id: Self::MESSAGE_ID,
+            command: Self::command(Self::MESSAGE_ID),
+            quiet: false,
+        }
+    }
+}
+
+impl StartTls for Ldap {
+    fn command(&self) -> &[u8] {
+        &self.command
+    }
+
+    fn is_binary(&self) -> bool {
+        true
+    }
+
+    /// LDAP has no greeting, there is nothing to discard.
+    fn is_greeting_complete(&mut self, _bytes: &[u8]) -> Option<usize> {
+        Some(0)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        // the response is complete once the whole outer SEQUENCE has
+        // been received
+        let n = find_message_end(bytes)?;
+
+        // a response to another message is not the StartTLS one,
+        // even if it is an extended response
+        let Some((id, code, message)) =
+            parse_response(&bytes[..=n]).filter(|(id, _, _)| *id == self.message_id)
+        else {
+            let bytes = bytes[..=n].to_vec();
+            return Some(Completion::new(
+                n + 1,
+                Err(UpgradeTlsError::InvalidResponse(bytes)),
+            ));
+        };
+
+        let message = String::from_utf8_lossy(message);
+
+        if !self.quiet {
+            debug!("receive response {id} with result code {code}: {message:?}");
+        }
+
+        if code == Self::SUCCESS {
+            return Some(Completion::new(n + 1, Ok(())));
+        }
+
+        let err = UpgradeTlsError::Rejected {
+            status: code.to_string(),
+            line: message.into_owned(),
+        };
+
+        Some(Completion::new(n + 1, Err(err)))
+    }
+}
+
+#[cfg(feature = "io-stream")]
+impl Upgrade<Ldap> {
+    /// Sets the message ID of the StartTLS extended request.
+    ///
+    /// Defaults to `1`. The server responds using the same message
+    /// ID, which may matter if the StartTLS operation is not the
+    /// first one sent on the connection.
+    ///
+    /// See also [`UpgradeTls::with_message_id`] for the builder
+    /// alternative.
+    pub fn set_message_id(&mut self, id: u32) {
+        let ldap = self.protocol_mut();
+        ldap.message_id = id;
+        ldap.command = Ldap::command(id);
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_message_id`].
+    pub fn with_message_id(mut self, id: u32) -> Self {
+        self.set_message_id(id);
+        self
+    }
+}
+
+/// Encodes the given number as a BER INTEGER content, using the
+/// minimum number of bytes.
+fn encode_integer(n: u32) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|b| **b == 0).count();
+    let mut int = bytes[skip..].to_vec();
+
+    // positive integers must not have their most significant bit set
+    if int[0] & 0x80 != 0 {
+        int.insert(0, 0);
+    }
+
+    int
+}
+
+/// Decodes the BER length located at the start of the given bytes.
+///
+/// Returns the decoded length alongside the number of bytes used to
+/// encode it, or `None` if more bytes are needed. Indefinite lengths
+/// are not allowed by LDAP, they are treated as never complete.
+fn decode_length(bytes: &[u8]) -> Option<(usize, usize)> {
+    let first = *bytes.first()?;
+
+    if first & 0x80 == 0 {
+        return Some((first as usize, 1));
+    }
+
+    let count = (first & 0x7f) as usize;
+
+    if count == 0 || count > 4 {
+        return None;
+    }
+
+    let len = bytes
+        .get(1..=count)?
+        .iter()
+        .fold(0, |len, b| (len << 8) | *b as usize);
+
+    Some((len, count + 1))
+}
+
+/// Finds the end of the first LDAP message.
+///
+/// Returns the index of the last byte of the outer SEQUENCE. A
+/// length overflowing `usize`, which may happen on 32-bit targets, is
+/// treated as never complete.
+fn find_message_end(bytes: &[u8]) -> Option<usize> {
+    let (len, header) = decode_length(bytes.get(1..)?)?;
+    let end = (1 + header).checked_add(len)?;
+
+    if bytes.len() < end {
+        return None;
+    }
+
+    Some(end - 1)
+}
+
+/// Reads the BER element with the given tag at the start of the
+/// given bytes.
+///
+/// Returns the content of the element alongside the remaining bytes.
+fn read_element(bytes: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    if *bytes.first()? != tag {
+        return None;
+    }
+
+    let (len, header) = decode_length(&bytes[1..])?;
+    let start = 1 + header;
+    let end = start.checked_add(len)?;
+    let content = bytes.get(start..end)?;
+    let rest = &bytes[end..];
+
+    Some((content, rest))
+}
+
+/// Decodes the given BER INTEGER (or ENUMERATED) content.
+///
+/// Values fit in 4 bytes, a 5th one is only allowed as the leading
+/// `0x00` padding the sign bit of values above `0x7fffffff`. Values
+/// that do not fit are rejected rather than truncated, which could
+/// make a bogus message ID or result code match.
+fn decode_integer(bytes: &[u8]) -> Option<u32> {
+    let bytes = match bytes {
+        [0, rest @ ..] if rest.len() == 4 => rest,
+        _ if (1..=4).contains(&bytes.len()) => bytes,
+        _ => return None,
+    };
+
+    Some(bytes.iter().fold(0, |n, b| (n << 8) | *b as u32))
+}
+
+/// Parses the given StartTLS extended response.
+///
+/// Returns the message ID, the result code and the diagnostic
+/// message.
+fn parse_response(bytes: &[u8]) -> Option<(u32, u32, &[u8])> {
+    let (message, _) = read_element(bytes, SEQUENCE)?;
+    let (id, rest) = read_element(message, INTEGER)?;
+    let (op, _) = read_element(rest, EXTENDED_RESPONSE)?;
+    let (code, rest) = read_element(op, ENUMERATED)?;
+    let (_matched_dn, rest) = read_element(rest, OCTET_STRING)?;
+    let (diagnostic, _) = read_element(rest, OCTET_STRING)?;
+    Some((decode_integer(id)?, decode_integer(code)?, diagnostic))
+}
@@ -1,5 +1,67 @@
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 #![doc = include_str!("../README.md")]
+#![no_std]
 
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[macro_use]
+mod macros;
+
+mod error;
+mod event;
+mod line_ending;
+#[cfg(feature = "futures")]
+mod runtime;
+mod upgrade;
+
+#[cfg(feature = "ftp")]
+pub mod ftp;
 #[cfg(feature = "imap")]
 pub mod imap;
+#[cfg(feature = "irc")]
+pub mod irc;
+#[cfg(feature = "ldap")]
+pub mod ldap;
+#[cfg(feature = "lmtp")]
+pub mod lmtp;
+#[cfg(feature = "mysql")]
+pub mod mysql;
+#[cfg(feature = "nntp")]
+pub mod nntp;
+#[cfg(feature = "pop3")]
+pub mod pop3;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+mod protocol;
+pub mod sans_io;
+#[cfg(feature = "sieve")]
+pub mod sieve;
+#[cfg(feature = "smtp")]
+pub mod smtp;
+#[cfg(feature = "xmpp")]
+pub mod xmpp;
+
+#[doc(inline)]
+pub use error::UpgradeTlsError;
+#[doc(inline)]
+pub use event::Event;
+#[doc(inline)]
+pub use line_ending::LineEnding;
+#[doc(inline)]
+pub use protocol::{recommended_mode, Mode, Protocol};
+#[cfg(feature = "io-stream")]
+#[doc(inline)]
+pub use protocol::{upgrade, AnyUpgrade};
+#[cfg(feature = "std")]
+#[doc(inline)]
+pub use upgrade::BlockingSteps;
+#[cfg(feature = "io-stream")]
+#[doc(inline)]
+pub use upgrade::Upgrade;
+#[doc(inline)]
+pub use upgrade::{
+    Completion, CompletionReport, Progress, StartTls, StartingState, Stats, Status,
+    DEFAULT_MAX_BUFFER,
+};
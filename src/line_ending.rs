@@ -0,0 +1,26 @@
+//! Module dedicated to the [`LineEnding`] type.
+
+use memchr::{memchr, memchr_iter};
+
+/// The line ending used to detect the end of greeting and response
+/// lines.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LineEnding {
+    /// Lines end with `\n`, whether it is preceded by `\r` or not.
+    #[default]
+    Lf,
+    /// Lines end with `\r\n`, a bare `\n` does not end a line.
+    CrLf,
+}
+
+impl LineEnding {
+    /// Finds the end of the first line of the given bytes.
+    ///
+    /// Returns the index of the `\n` terminating the line.
+    pub fn find(self, bytes: &[u8]) -> Option<usize> {
+        match self {
+            Self::Lf => memchr(b'\n', bytes),
+            Self::CrLf => memchr_iter(b'\n', bytes).find(|n| *n > 0 && bytes[n - 1] == b'\r'),
+        }
+    }
+}
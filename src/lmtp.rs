@@ -0,0 +1,186 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the LMTP
+//! protocol.
+//!
+//! LMTP is the local variant of SMTP defined in RFC 2033. It only
+//! differs by its LHLO command replacing EHLO, so replies are parsed
+//! the same way as SMTP ones.
+
+use alloc::{string::String, vec::Vec};
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{smtp::Smtp, Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain LMTP service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 24;
+
+/// The STARTTLS command written to the server.
+pub const STARTTLS_COMMAND: &[u8] = b"STARTTLS\r\n";
+
+/// The STARTTLS coroutine that upgrades a plain LMTP (TCP) stream to
+/// a secure one.
+///
+/// See [`crate::smtp::Ehlo::lhlo`] to collect the capabilities once
+/// the stream is upgraded.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Lmtp>;
+
+/// The LMTP flavor of the [`Upgrade`] coroutine.
+///
+/// It wraps the SMTP flavor, which sends LHLO in place of EHLO, so
+/// that replies are handled the same way by both protocols.
+#[derive(Clone, Debug)]
+pub struct Lmtp(Smtp);
+
+impl Lmtp {
+    /// Tells the coroutine to send LHLO with the given client domain
+    /// before the STARTTLS command.
+    ///
+    /// See [`Upgrade::set_lhlo_domain`].
+    pub fn set_lhlo(&mut self, domain: impl Into<String>) {
+        self.0.set_ehlo(domain);
+    }
+
+    /// Builder alternative to [`Lmtp::set_lhlo`].
+    pub fn with_lhlo(mut self, domain: impl Into<String>) -> Self {
+        self.set_lhlo(domain);
+        self
+    }
+
+    /// Tells the coroutine to send LHLO before the STARTTLS command.
+    ///
+    /// See [`Upgrade::set_lhlo_enabled`].
+    pub fn set_lhlo_enabled(&mut self, enabled: bool) {
+        self.0.set_ehlo_enabled(enabled);
+    }
+
+    /// Builder alternative to [`Lmtp::set_lhlo_enabled`].
+    pub fn with_lhlo_enabled(mut self, enabled: bool) -> Self {
+        self.set_lhlo_enabled(enabled);
+        self
+    }
+}
+
+impl Default for Lmtp {
+    fn default() -> Self {
+        Self(Smtp::from_verb("LHLO"))
+    }
+}
+
+/// Forwards the protocol to the SMTP one, except for its name.
+impl StartTls for Lmtp {
+    fn command(&self) -> &[u8] {
+        self.0.command()
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        self.0.is_greeting_complete(bytes)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        self.0.is_response_complete(bytes)
+    }
+
+    fn prelude(&mut self) -> Result<Option<Vec<u8>>, UpgradeTlsError> {
+        self.0.prelude()
+    }
+
+    fn validate(&self) -> Result<(), UpgradeTlsError> {
+        self.0.validate()
+    }
+
+    fn has_prelude(&self) -> bool {
+        self.0.has_prelude()
+    }
+
+    fn is_prelude_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        self.0.is_prelude_response_complete(bytes)
+    }
+
+    fn status<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        self.0.status(line)
+    }
+
+    fn is_binary(&self) -> bool {
+        self.0.is_binary()
+    }
+
+    fn drain_offset(&self, bytes: &[u8]) -> Option<usize> {
+        self.0.drain_offset(bytes)
+    }
+
+    fn drained(&mut self, n: usize) {
+        self.0.drained(n)
+    }
+
+    fn set_quiet(&mut self, quiet: bool) {
+        self.0.set_quiet(quiet)
+    }
+
+    fn set_max_buffer(&mut self, max: usize) {
+        self.0.set_max_buffer(max)
+    }
+
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+
+    fn tag(&self) -> Option<&str> {
+        self.0.tag()
+    }
+
+    fn tls_required(&self) -> Option<bool> {
+        self.0.tls_required()
+    }
+
+    fn capabilities(&self) -> &[String] {
+        self.0.capabilities()
+    }
+
+    fn is_transient(&self, line: &str) -> bool {
+        self.0.is_transient(line)
+    }
+
+    fn retry(&mut self) {
+        self.0.retry()
+    }
+}
+
+#[cfg(feature = "io-stream")]
+impl Upgrade<Lmtp> {
+    /// Tells the coroutine to send LHLO with the given client domain
+    /// before the STARTTLS command.
+    ///
+    /// Defaults to [`crate::smtp::DEFAULT_EHLO_DOMAIN`], like the
+    /// EHLO domain of SMTP, see
+    /// [`crate::smtp::UpgradeTls::set_ehlo_domain`].
+    ///
+    /// See also [`UpgradeTls::with_lhlo_domain`] for the builder
+    /// alternative.
+    pub fn set_lhlo_domain(&mut self, domain: impl Into<String>) {
+        self.protocol_mut().set_lhlo(domain);
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_lhlo_domain`].
+    pub fn with_lhlo_domain(mut self, domain: impl Into<String>) -> Self {
+        self.set_lhlo_domain(domain);
+        self
+    }
+
+    /// Tells the coroutine to send LHLO before the STARTTLS command.
+    ///
+    /// Enabled by default, like EHLO for SMTP, see
+    /// [`crate::smtp::UpgradeTls::set_ehlo_enabled`].
+    ///
+    /// See also [`UpgradeTls::with_lhlo_enabled`] for the builder
+    /// alternative.
+    pub fn set_lhlo_enabled(&mut self, enabled: bool) {
+        self.protocol_mut().set_lhlo_enabled(enabled);
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_lhlo_enabled`].
+    pub fn with_lhlo_enabled(mut self, enabled: bool) -> Self {
+        self.set_lhlo_enabled(enabled);
+        self
+    }
+}
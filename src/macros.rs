@@ -0,0 +1,59 @@
+//! Module dedicated to internal logging macros.
+//!
+//! Logging relies either on the [`tracing`] crate when the `tracing`
+//! feature is enabled, or on the [`log`] crate when the `log` feature
+//! is enabled. Otherwise, messages are still type checked but never
+//! built, so that no bytes get decoded for nothing.
+
+/// Logs the given message at debug level.
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        ::tracing::debug!($($arg)*)
+    };
+}
+
+/// Logs the given message at debug level.
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        ::log::debug!($($arg)*)
+    };
+}
+
+/// Logs the given message at debug level.
+#[cfg(not(any(feature = "log", feature = "tracing")))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = ::core::format_args!($($arg)*);
+        }
+    };
+}
+
+/// Logs the given bytes discarded by a step of the given protocol.
+///
+/// Using `tracing`, the protocol name, the tag and the number of
+/// bytes are emitted as event fields.
+macro_rules! discard {
+    ($step:literal, $protocol:expr, $bytes:expr) => {{
+        let bytes: &[u8] = $bytes;
+
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!(
+            protocol = $protocol.name(),
+            tag = $protocol.tag(),
+            bytes = bytes.len(),
+            "discard {} {:?}",
+            $step,
+            ::alloc::string::String::from_utf8_lossy(bytes),
+        );
+
+        #[cfg(not(feature = "tracing"))]
+        debug!(
+            "discard {} {:?}",
+            $step,
+            ::alloc::string::String::from_utf8_lossy(bytes)
+        );
+    }};
+}
@@ -0,0 +1,238 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the MySQL
+//! protocol.
+//!
+//! MySQL has no STARTTLS command: once the server sent its initial
+//! handshake packet, the client sends an SSL request packet with the
+//! `CLIENT_SSL` capability flag set, then directly starts the TLS
+//! handshake. The server does not answer the SSL request.
+//!
+//! See <https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_connection_phase.html>.
+
+use alloc::{
+    borrow::ToOwned,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use memchr::memchr;
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain MySQL service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 3306;
+
+/// The length of a packet header: 3 bytes of payload length followed
+/// by 1 byte of sequence ID.
+const HEADER_LEN: usize = 4;
+
+/// The protocol version of the initial handshake packet.
+const PROTOCOL_VERSION: u8 = 10;
+
+/// The first payload byte of an error packet.
+const ERR_MARKER: u8 = 0xff;
+
+/// The capability flag telling that TLS is supported.
+const CLIENT_SSL: u32 = 0x0000_0800;
+
+/// The maximum size of packets the client wants to send.
+const MAX_PACKET_SIZE: u32 = 0x0100_0000;
+
+/// The STARTTLS coroutine that upgrades a plain MySQL (TCP) stream to
+/// a secure one.
+///
+/// The greeting is the initial handshake packet, which tells if the
+/// server supports TLS. It should be discarded by the coroutine,
+/// unless it has already been read before.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Mysql>;
+
+/// The MySQL flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug)]
+pub struct Mysql {
+    capabilities: u32,
+    charset: u8,
+    sequence_id: u8,
+    command: Vec<u8>,
+    handshake: Option<Result<(), UpgradeTlsError>>,
+}
+
+impl Mysql {
+    /// The default capability flags: `CLIENT_PROTOCOL_41`,
+    /// `CLIENT_SECURE_CONNECTION`, `CLIENT_PLUGIN_AUTH` and
+    /// `CLIENT_SSL`.
+    const CAPABILITIES: u32 = 0x0000_0200 | 0x0000_8000 | 0x0008_0000 | CLIENT_SSL;
+
+    /// The default character set (`utf8mb4_general_ci`).
+    const CHARSET: u8 = 45;
+
+    /// Builds the SSL request packet.
+    fn command(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(HEADER_LEN + 32);
+
+        // the payload is always 32 bytes long
+        packet.extend([32, 0, 0, self.sequence_id]);
+        packet.extend((self.capabilities | CLIENT_SSL).to_le_bytes());
+        packet.extend(MAX_PACKET_SIZE.to_le_bytes());
+        packet.push(self.charset);
+        packet.extend([0; 23]);
+
+        packet
+    }
+}
+
+impl Default for Mysql {
+    fn default() -> Self {
+        let mut mysql = Self {
+            capabilities: Self::CAPABILITIES,
+            charset: Self::CHARSET,
+            sequence_id: 1,
+            command: Vec::new(),
+            handshake: None,
+        };
+
+        mysql.command = mysql.command();
+        mysql
+    }
+}
+
+impl StartTls for Mysql {
+    fn command(&self) -> &[u8] {
+        &self.command
+    }
+
+    fn is_binary(&self) -> bool {
+        true
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        let n = find_packet_end(bytes)?;
+        let payload = &bytes[HEADER_LEN..n];
+
+        // the SSL request follows the initial handshake packet
+        self.sequence_id = bytes[3].wrapping_add(1);
+        self.command = self.command();
+        self.handshake = Some(parse_handshake(payload));
+
+        Some(n)
+    }
+
+    fn prelude(&mut self) -> Result<Option<Vec<u8>>, UpgradeTlsError> {
+        match self.handshake.take() {
+            Some(Err(err)) => Err(err),
+            _ => Ok(None),
+        }
+    }
+
+    /// The server does not answer the SSL request, the TLS handshake
+    /// can start right away.
+    fn is_response_complete(&mut self, _bytes: &[u8]) -> Option<Completion> {
+        Some(Completion::new(0, Ok(())))
+    }
+
+    fn reset(&mut self) {
+        self.handshake = None;
+    }
+}
+
+#[cfg(feature = "io-stream")]
+impl Upgrade<Mysql> {
+    /// Sets the capability flags of the SSL request packet.
+    ///
+    /// The flags need to match the ones sent later in the handshake
+    /// response packet. The `CLIENT_SSL` flag is always set.
+    ///
+    /// See also [`UpgradeTls::with_capabilities`] for the builder
+    /// alternative.
+    pub fn set_capabilities(&mut self, capabilities: u32) {
+        let mysql = self.protocol_mut();
+        mysql.capabilities = capabilities;
+        mysql.command = mysql.command();
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_capabilities`].
+    pub fn with_capabilities(mut self, capabilities: u32) -> Self {
+        self.set_capabilities(capabilities);
+        self
+    }
+
+    /// Sets the character set of the SSL request packet.
+    ///
+    /// Defaults to `45` (`utf8mb4_general_ci`).
+    ///
+    /// See also [`UpgradeTls::with_charset`] for the builder
+    /// alternative.
+    pub fn set_charset(&mut self, charset: u8) {
+        let mysql = self.protocol_mut();
+        mysql.charset = charset;
+        mysql.command = mysql.command();
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_charset`].
+    pub fn with_charset(mut self, charset: u8) -> Self {
+        self.set_charset(charset);
+        self
+    }
+}
+
+/// Finds the end of the first packet.
+///
+/// The payload length is read from the 3-byte little-endian header,
+/// so the packet is only complete once the whole header and the whole
+/// payload have been received. Returns the index right after the
+/// last byte of the packet.
+fn find_packet_end(bytes: &[u8]) -> Option<usize> {
+    let header = bytes.get(..HEADER_LEN)?;
+    let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    let end = HEADER_LEN + len;
+
+    if bytes.len() < end {
+        return None;
+    }
+
+    Some(end)
+}
+
+/// Parses the given initial handshake payload.
+///
+/// Succeeds if the server supports TLS. The server may also send an
+/// error packet instead, for example when the client host is not
+/// allowed to connect.
+fn parse_handshake(payload: &[u8]) -> Result<(), UpgradeTlsError> {
+    let invalid = || UpgradeTlsError::InvalidResponse(payload.to_vec());
+
+    match payload.first() {
+        Some(&PROTOCOL_VERSION) => (),
+        Some(&ERR_MARKER) => {
+            // error code (2 bytes), then `#` followed by the SQL
+            // state (5 bytes), then the message
+            let code = payload.get(1..3).ok_or_else(invalid)?;
+            let code = u16::from_le_bytes([code[0], code[1]]);
+            let message = payload.get(3..).unwrap_or_default();
+            let message = message
+                .strip_prefix(b"#")
+                .map_or(message, |m| m.get(5..).unwrap_or_default());
+
+            return Err(UpgradeTlsError::Rejected {
+                status: code.to_string(),
+                line: String::from_utf8_lossy(message).trim_end().to_owned(),
+            });
+        }
+        _ => return Err(invalid()),
+    }
+
+    // protocol version, then the NUL-terminated server version, the
+    // connection ID (4 bytes), the first part of the auth plugin
+    // data (8 bytes) and a filler (1 byte)
+    let version_end = memchr(0, &payload[1..]).ok_or_else(invalid)? + 1;
+    let flags = version_end + 1 + 4 + 8 + 1;
+    let flags = payload.get(flags..flags + 2).ok_or_else(invalid)?;
+    let flags = u16::from_le_bytes([flags[0], flags[1]]) as u32;
+
+    if flags & CLIENT_SSL == 0 {
+        return Err(UpgradeTlsError::StartTlsNotAdvertised);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the NNTP
+//! protocol.
+
+use alloc::{borrow::ToOwned, string::String};
+
+use memchr::memchr;
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain NNTP service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 119;
+
+/// The STARTTLS command written to the server.
+pub const STARTTLS_COMMAND: &[u8] = b"STARTTLS\r\n";
+
+/// The STARTTLS coroutine that upgrades a plain NNTP (TCP) stream to
+/// a secure one.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Nntp>;
+
+/// The NNTP flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug, Default)]
+pub struct Nntp;
+
+impl StartTls for Nntp {
+    fn command(&self) -> &[u8] {
+        STARTTLS_COMMAND
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        memchr(b'\n', bytes).map(|n| n + 1)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        // NNTP responses are not tagged: the response is the first
+        // line starting with a status code
+        let (start, end) = find_status_line(bytes)?;
+        let line = &bytes[start..=end];
+        let status = &line[..3];
+
+        if status == b"382" {
+            return Some(Completion::new(end + 1, Ok(())));
+        }
+
+        let err = UpgradeTlsError::Rejected {
+            status: String::from_utf8_lossy(status).into_owned(),
+            line: String::from_utf8_lossy(line).trim_end().to_owned(),
+        };
+
+        Some(Completion::new(end + 1, Err(err)))
+    }
+
+    /// Prelude commands succeed with any informative (`1xx`) or
+    /// positive completion (`2xx`) response, like `101` for
+    /// `CAPABILITIES`.
+    fn is_prelude_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let (start, end) = find_status_line(bytes)?;
+        let line = &bytes[start..=end];
+
+        if line[0] == b'1' || line[0] == b'2' {
+            return Some(Completion::new(end + 1, Ok(())));
+        }
+
+        let err = UpgradeTlsError::Rejected {
+            status: String::from_utf8_lossy(&line[..3]).into_owned(),
+            line: String::from_utf8_lossy(line).trim_end().to_owned(),
+        };
+
+        Some(Completion::new(end + 1, Err(err)))
+    }
+}
+
+/// Finds the first NNTP status line.
+///
+/// A status line is a line starting with a 3-digit status code, like
+/// `382 Continue with TLS negotiation`. Returns the index of the
+/// first byte of the status line and the index of the `\n`
+/// terminating it.
+fn find_status_line(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 0;
+
+    while let Some(n) = memchr(b'\n', &bytes[start..]) {
+        let end = start + n;
+        let line = &bytes[start..end];
+
+        if line.len() >= 3 && line[..3].iter().all(u8::is_ascii_digit) {
+            return Some((start, end));
+        }
+
+        start = end + 1;
+    }
+
+    None
+}
@@ -0,0 +1,181 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the POP3
+//! protocol.
+
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+
+use memchr::memchr;
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain POP3 service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 110;
+
+/// The STARTTLS command written to the server.
+pub const STARTTLS_COMMAND: &[u8] = b"STLS\r\n";
+
+/// The STLS coroutine that upgrades a plain POP3 (TCP) stream to a
+/// secure one.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Pop3>;
+
+/// The POP3 flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug, Default)]
+pub struct Pop3 {
+    check_capability: bool,
+    capabilities: Vec<String>,
+    capability_requested: bool,
+}
+
+impl Pop3 {
+    /// Returns `true` if the `STLS` capability has been advertised by
+    /// the server.
+    fn has_stls_capability(&self) -> bool {
+        self.capabilities.iter().any(|capability| {
+            let name = capability.split_whitespace().next().unwrap_or_default();
+            name.eq_ignore_ascii_case("STLS")
+        })
+    }
+}
+
+impl StartTls for Pop3 {
+    fn command(&self) -> &[u8] {
+        STARTTLS_COMMAND
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        memchr(b'\n', bytes).map(|n| n + 1)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        // POP3 responses are not tagged: the first line received
+        // after the command is the response
+        let n = memchr(b'\n', bytes)?;
+        Some(Completion::new(n + 1, status_result(&bytes[..=n])))
+    }
+
+    fn prelude(&mut self) -> Result<Option<Vec<u8>>, UpgradeTlsError> {
+        if !self.check_capability {
+            return Ok(None);
+        }
+
+        if !self.capability_requested {
+            self.capability_requested = true;
+            return Ok(Some(b"CAPA\r\n".to_vec()));
+        }
+
+        if !self.has_stls_capability() {
+            return Err(UpgradeTlsError::StartTlsNotAdvertised);
+        }
+
+        Ok(None)
+    }
+
+    fn has_prelude(&self) -> bool {
+        self.check_capability
+    }
+
+    /// The CAPA response is a multiline response: capabilities are
+    /// listed one per line after the status line, and terminated by a
+    /// line containing only a dot.
+    fn is_prelude_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let n = memchr(b'\n', bytes)?;
+
+        // a negative response is never followed by any line
+        if let Err(err) = status_result(&bytes[..=n]) {
+            return Some(Completion::new(n + 1, Err(err)));
+        }
+
+        let mut capabilities = Vec::new();
+        let mut start = n + 1;
+
+        while let Some(n) = memchr(b'\n', &bytes[start..]) {
+            let end = start + n;
+            let line = String::from_utf8_lossy(&bytes[start..end]);
+            let line = line.trim_end();
+
+            if line == "." {
+                self.capabilities = capabilities;
+                return Some(Completion::new(end + 1, Ok(())));
+            }
+
+            if !line.is_empty() {
+                capabilities.push(line.to_owned());
+            }
+
+            start = end + 1;
+        }
+
+        None
+    }
+
+    fn reset(&mut self) {
+        self.capabilities.clear();
+        self.capability_requested = false;
+    }
+
+    fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+
+    /// Rejections are transient when they hold the `[IN-USE]` or
+    /// `[SYS/TEMP]` response codes (RFC 3206), like `-ERR [IN-USE]
+    /// mailbox locked`.
+    fn is_transient(&self, line: &str) -> bool {
+        let line = line.to_ascii_uppercase();
+        line.contains("[IN-USE]") || line.contains("[SYS/TEMP]")
+    }
+}
+
+#[cfg(feature = "io-stream")]
+impl Upgrade<Pop3> {
+    /// Tells the coroutine to check the server capabilities before
+    /// sending the STLS command.
+    ///
+    /// By default, the STLS command is sent without checking
+    /// capabilities. When enabled, capabilities are taken from the
+    /// response of a CAPA command. If the `STLS` capability is not
+    /// advertised, the coroutine fails with
+    /// [`UpgradeTlsError::StartTlsNotAdvertised`].
+    ///
+    /// See also [`UpgradeTls::with_check_capability`] for the builder
+    /// alternative.
+    pub fn check_capability(&mut self, check: bool) {
+        self.protocol_mut().check_capability = check;
+    }
+
+    /// Builder alternative to [`UpgradeTls::check_capability`].
+    pub fn with_check_capability(mut self, check: bool) -> Self {
+        self.check_capability(check);
+        self
+    }
+
+    /// Returns the capabilities advertised by the server.
+    ///
+    /// Every capability is a line of the CAPA response, like `STLS`
+    /// or `SASL PLAIN`. Capabilities are only collected when
+    /// [`UpgradeTls::check_capability`] is enabled, this slice is
+    /// empty otherwise.
+    pub fn capabilities(&self) -> &[String] {
+        &self.protocol().capabilities
+    }
+}
+
+/// Builds the result of the given status line, which succeeds if its
+/// status is `+OK`, case-insensitively.
+fn status_result(line: &[u8]) -> Result<(), UpgradeTlsError> {
+    let status = line
+        .split(u8::is_ascii_whitespace)
+        .next()
+        .unwrap_or_default();
+
+    if status.eq_ignore_ascii_case(b"+OK") {
+        return Ok(());
+    }
+
+    Err(UpgradeTlsError::Rejected {
+        status: String::from_utf8_lossy(status).into_owned(),
+        line: String::from_utf8_lossy(line).trim_end().to_owned(),
+    })
+}
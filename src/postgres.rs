@@ -0,0 +1,64 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the
+//! PostgreSQL protocol.
+//!
+//! PostgreSQL has no STARTTLS command, but rather an SSLRequest
+//! packet sent before the startup message. There is no greeting: the
+//! client directly sends the request, then the server answers with a
+//! single byte, `S` to proceed or `N` to refuse.
+//!
+//! See <https://www.postgresql.org/docs/current/protocol-flow.html#PROTOCOL-FLOW-SSL>.
+
+use alloc::{string::String, vec::Vec};
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain PostgreSQL service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 5432;
+
+/// The SSLRequest packet written to the server: the packet length
+/// (8) followed by the SSLRequest code (80877103), both as big-endian
+/// 32-bit integers.
+pub const STARTTLS_COMMAND: &[u8] = &[0x00, 0x00, 0x00, 0x08, 0x04, 0xD2, 0x16, 0x2F];
+
+/// The SSLRequest coroutine that upgrades a plain PostgreSQL (TCP)
+/// stream to a secure one.
+///
+/// The server is not supposed to send anything after the response
+/// byte and before the TLS handshake: enabling
+/// [`Upgrade::set_strict_buffer`] is recommended.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Postgres>;
+
+/// The PostgreSQL flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug, Default)]
+pub struct Postgres;
+
+impl StartTls for Postgres {
+    fn command(&self) -> &[u8] {
+        STARTTLS_COMMAND
+    }
+
+    fn is_binary(&self) -> bool {
+        true
+    }
+
+    /// PostgreSQL has no greeting, there is nothing to discard.
+    fn is_greeting_complete(&mut self, _bytes: &[u8]) -> Option<usize> {
+        Some(0)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let result = match bytes.first()? {
+            b'S' => Ok(()),
+            b'N' => Err(UpgradeTlsError::Rejected {
+                status: String::from("N"),
+                line: String::from("N"),
+            }),
+            byte => Err(UpgradeTlsError::InvalidResponse(Vec::from([*byte]))),
+        };
+
+        Some(Completion::new(1, result))
+    }
+}
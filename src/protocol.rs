@@ -0,0 +1,176 @@
+//! Module dedicated to the [`Protocol`] enum and the TLS [`Mode`]
+//! heuristic.
+
+#[cfg(feature = "io-stream")]
+use alloc::boxed::Box;
+
+#[cfg(feature = "io-stream")]
+use crate::{StartTls, Upgrade};
+
+/// The STARTTLS coroutine of a protocol selected at runtime, see
+/// [`Protocol::upgrade`].
+#[cfg(feature = "io-stream")]
+pub type AnyUpgrade = Upgrade<Box<dyn StartTls + Send>>;
+
+/// The protocols supported by the crate, one per enabled feature.
+///
+/// This allows applications supporting several protocols to select
+/// one at runtime, like from a configuration file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Protocol {
+    /// The File Transfer Protocol, see [`crate::ftp`].
+    #[cfg(feature = "ftp")]
+    Ftp,
+    /// The Internet Message Access Protocol, see [`crate::imap`].
+    #[cfg(feature = "imap")]
+    Imap,
+    /// The Internet Relay Chat protocol, see [`crate::irc`].
+    #[cfg(feature = "irc")]
+    Irc,
+    /// The Lightweight Directory Access Protocol, see
+    /// [`crate::ldap`].
+    #[cfg(feature = "ldap")]
+    Ldap,
+    /// The Local Mail Transfer Protocol, see [`crate::lmtp`].
+    #[cfg(feature = "lmtp")]
+    Lmtp,
+    /// The MySQL client/server protocol, see [`crate::mysql`].
+    #[cfg(feature = "mysql")]
+    Mysql,
+    /// The Network News Transfer Protocol, see [`crate::nntp`].
+    #[cfg(feature = "nntp")]
+    Nntp,
+    /// The Post Office Protocol, see [`crate::pop3`].
+    #[cfg(feature = "pop3")]
+    Pop3,
+    /// The PostgreSQL frontend/backend protocol, see
+    /// [`crate::postgres`].
+    #[cfg(feature = "postgres")]
+    Postgres,
+    /// The ManageSieve protocol, see [`crate::sieve`].
+    #[cfg(feature = "sieve")]
+    Sieve,
+    /// The Simple Mail Transfer Protocol, see [`crate::smtp`].
+    #[cfg(feature = "smtp")]
+    Smtp,
+    /// The Extensible Messaging and Presence Protocol, see
+    /// [`crate::xmpp`].
+    #[cfg(feature = "xmpp")]
+    Xmpp,
+}
+
+impl Protocol {
+    /// Creates a new STARTTLS coroutine for the protocol, with the
+    /// same defaults as [`Upgrade::new`].
+    ///
+    /// This allows applications supporting several protocols to
+    /// select the coroutine at runtime. Protocol-specific options,
+    /// like the IMAP tag, are not reachable from the returned
+    /// coroutine: [`Upgrade::from_protocol`] can be used instead with
+    /// a configured protocol.
+    #[cfg(feature = "io-stream")]
+    pub fn upgrade(self) -> AnyUpgrade {
+        Upgrade::from_protocol(self.into_start_tls())
+    }
+
+    /// Returns the default implementation of the protocol.
+    #[cfg(feature = "io-stream")]
+    fn into_start_tls(self) -> Box<dyn StartTls + Send> {
+        match self {
+            #[cfg(feature = "ftp")]
+            Self::Ftp => Box::new(crate::ftp::Ftp),
+            #[cfg(feature = "imap")]
+            Self::Imap => Box::new(crate::imap::Imap::default()),
+            #[cfg(feature = "irc")]
+            Self::Irc => Box::new(crate::irc::Irc),
+            #[cfg(feature = "ldap")]
+            Self::Ldap => Box::new(crate::ldap::Ldap::default()),
+            #[cfg(feature = "lmtp")]
+            Self::Lmtp => Box::new(crate::lmtp::Lmtp::default()),
+            #[cfg(feature = "mysql")]
+            Self::Mysql => Box::new(crate::mysql::Mysql::default()),
+            #[cfg(feature = "nntp")]
+            Self::Nntp => Box::new(crate::nntp::Nntp),
+            #[cfg(feature = "pop3")]
+            Self::Pop3 => Box::new(crate::pop3::Pop3::default()),
+            #[cfg(feature = "postgres")]
+            Self::Postgres => Box::new(crate::postgres::Postgres),
+            #[cfg(feature = "sieve")]
+            Self::Sieve => Box::new(crate::sieve::Sieve),
+            #[cfg(feature = "smtp")]
+            Self::Smtp => Box::new(crate::smtp::Smtp::default()),
+            #[cfg(feature = "xmpp")]
+            Self::Xmpp => Box::new(crate::xmpp::Xmpp),
+        }
+    }
+
+    /// Returns the well-known port of the implicit TLS variant of the
+    /// protocol, if any.
+    ///
+    /// Protocols negotiating TLS within their own handshake, like
+    /// MySQL or Postgres, or without any implicit TLS variant, like
+    /// LMTP or ManageSieve, do not have such port.
+    pub fn implicit_tls_port(self) -> Option<u16> {
+        match self {
+            #[cfg(feature = "ftp")]
+            Self::Ftp => Some(990),
+            #[cfg(feature = "imap")]
+            Self::Imap => Some(993),
+            #[cfg(feature = "irc")]
+            Self::Irc => Some(6697),
+            #[cfg(feature = "ldap")]
+            Self::Ldap => Some(636),
+            #[cfg(feature = "lmtp")]
+            Self::Lmtp => None,
+            #[cfg(feature = "mysql")]
+            Self::Mysql => None,
+            #[cfg(feature = "nntp")]
+            Self::Nntp => Some(563),
+            #[cfg(feature = "pop3")]
+            Self::Pop3 => Some(995),
+            #[cfg(feature = "postgres")]
+            Self::Postgres => None,
+            #[cfg(feature = "sieve")]
+            Self::Sieve => None,
+            #[cfg(feature = "smtp")]
+            Self::Smtp => Some(465),
+            #[cfg(feature = "xmpp")]
+            Self::Xmpp => Some(5223),
+        }
+    }
+}
+
+/// The way TLS is established on a connection.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Mode {
+    /// TLS is established right after connecting, before any byte of
+    /// the protocol is exchanged. The STARTTLS coroutine is not
+    /// needed.
+    Implicit,
+    /// The connection starts in plain text, then gets upgraded by the
+    /// STARTTLS coroutine.
+    StartTls,
+}
+
+/// Returns the recommended TLS mode for the given port and protocol.
+///
+/// The heuristic relies on the well-known port mapping: ports
+/// dedicated to implicit TLS (like 465 for SMTP, 993 for IMAP or 995
+/// for POP3) are expected to be secure from the start, while any
+/// other port (like 587, 143 or 110) is expected to be upgraded via
+/// STARTTLS.
+pub fn recommended_mode(port: u16, protocol: Protocol) -> Mode {
+    if protocol.implicit_tls_port() == Some(port) {
+        Mode::Implicit
+    } else {
+        Mode::StartTls
+    }
+}
+
+/// Creates a new STARTTLS coroutine for the given protocol.
+///
+/// See [`Protocol::upgrade`].
+#[cfg(feature = "io-stream")]
+pub fn upgrade(protocol: Protocol) -> AnyUpgrade {
+    protocol.upgrade()
+}
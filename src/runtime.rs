@@ -0,0 +1,38 @@
+//! Module dedicated to the I/O glue of runtimes not covered by
+//! [`io_stream`].
+//!
+//! Each glue processes the I/O requests emitted by coroutines the
+//! same way [`io_stream`] runtimes do: a read request is answered
+//! with the bytes read into the given buffer, a write request with
+//! the number of bytes written from the given buffer.
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use io_stream::{Io, Output};
+
+/// Processes the given I/O request against the given [`futures`]
+/// stream.
+pub(crate) async fn handle_futures(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    io: Io,
+) -> std::io::Result<Io> {
+    match io {
+        Io::Read(Err(mut buffer)) => {
+            let bytes_count = stream.read(&mut buffer).await?;
+            Ok(Io::Read(Ok(Output {
+                buffer,
+                bytes_count,
+            })))
+        }
+        Io::Write(Err(buffer)) => {
+            let bytes_count = stream.write(&buffer).await?;
+            Ok(Io::Write(Ok(Output {
+                buffer,
+                bytes_count,
+            })))
+        }
+        Io::Error(err) => Err(std::io::Error::other(err)),
+        io => Err(std::io::Error::other(alloc::format!(
+            "unexpected I/O request {io:?}"
+        ))),
+    }
+}
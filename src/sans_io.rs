@@ -0,0 +1,1049 @@
+//! Module dedicated to the sans-io [`Flow`] of the STARTTLS
+//! upgrade.
+//!
+//! The flow holds all the decisions of the
+//! [`Upgrade`](crate::Upgrade) coroutine, without depending on any
+//! I/O model: bytes read from the transport are fed to
+//! [`Flow::advance`], which tells what to do next. This allows the
+//! flow to be driven by transports [`io_stream`] does not cover. The
+//! [`Upgrade`](crate::Upgrade) coroutine is a thin adapter over this
+//! flow and the [`io_stream`] coroutines.
+//!
+//! The flow is always available, while the coroutines require the
+//! `io-stream` cargo feature (enabled by default). Disabling it
+//! drops the [`io_stream`] dependency: the flow is then built via
+//! [`Flow::new`] or [`Flow::from_protocol`], and configured via its
+//! own builders.
+
+use alloc::{borrow::ToOwned, format, string::String, sync::Arc, vec::Vec};
+use core::{fmt, mem};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+#[cfg(feature = "std")]
+use crate::event::Observer;
+use crate::{
+    upgrade::{Completion, Progress, StartingState, DEFAULT_MAX_BUFFER},
+    Event, StartTls, UpgradeTlsError,
+};
+
+/// The next thing to do after advancing a [`Flow`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// More bytes need to be read from the transport, then fed to the
+    /// flow.
+    NeedMore,
+    /// The given bytes need to be fully written to the transport,
+    /// then the flow needs to be advanced again.
+    Write(Vec<u8>),
+    /// The flow completed, the result tells if the server accepted
+    /// the STARTTLS command or not.
+    Done(Result<(), UpgradeTlsError>),
+}
+
+/// Internal state of the [`Flow`].
+#[derive(Clone, Debug)]
+enum State {
+    /// The flow did not start yet.
+    Begin,
+    /// The greeting needs to be discarded.
+    DiscardGreeting,
+    /// A prelude command has been handed over to be written.
+    WritePreludeCommand,
+    /// The prelude command response needs to be read.
+    ReadPreludeResponse,
+    /// The STARTTLS command has been handed over to be written.
+    WriteStartTlsCommand,
+    /// The STARTTLS response needs to be discarded.
+    DiscardResponse,
+}
+
+/// The sans-io flow of the STARTTLS upgrade.
+///
+/// The flow is configured via the [`Upgrade`](crate::Upgrade)
+/// builders, then obtained with
+/// [`Upgrade::into_flow`](crate::Upgrade::into_flow).
+#[derive(Clone, Debug)]
+pub struct Flow<P> {
+    pub(crate) protocol: P,
+    state: State,
+    need_more: bool,
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) greeting: Option<String>,
+    pub(crate) response: Option<String>,
+    pub(crate) discard_greeting: bool,
+    pub(crate) prelude: Vec<Vec<u8>>,
+    prelude_sent: usize,
+    pub(crate) command: Option<Vec<u8>>,
+    pub(crate) skip_command: bool,
+    pub(crate) strict_buffer: bool,
+    pub(crate) strict_ascii: bool,
+    pub(crate) drain_lines: bool,
+    pub(crate) quiet: bool,
+    pub(crate) max_reads: Option<usize>,
+    pub(crate) max_buffer: usize,
+    reads: usize,
+    pub(crate) progress: Progress,
+    pub(crate) transcript: Option<Transcript>,
+    #[cfg(feature = "std")]
+    pub(crate) observer: Option<Observer>,
+    #[cfg(feature = "std")]
+    pub(crate) trailing_handler: Option<TrailingHandler>,
+    pub(crate) greeting_complete: Option<GreetingComplete>,
+    pub(crate) success_token: Option<String>,
+    pub(crate) retries: usize,
+    retried: usize,
+    pub(crate) retry_predicate: Option<RetryPredicate>,
+}
+
+impl<P: StartTls + Default> Flow<P> {
+    /// Creates a new flow with sane defaults.
+    ///
+    /// The flow is configured via its builders, which match the ones
+    /// of the [`Upgrade`](crate::Upgrade) coroutine, so that it can be
+    /// used without depending on [`io_stream`] at all.
+    pub fn new() -> Self {
+        Self::from_protocol(P::default())
+    }
+}
+
+impl<P: StartTls + Default> Default for Flow<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: StartTls> Flow<P> {
+    /// Creates a new flow for the given protocol.
+    pub fn from_protocol(protocol: P) -> Self {
+        Self {
+            protocol,
+            state: State::Begin,
+            need_more: false,
+            bytes: Vec::new(),
+            greeting: None,
+            response: None,
+            discard_greeting: false,
+            prelude: Vec::new(),
+            prelude_sent: 0,
+            command: None,
+            skip_command: false,
+            strict_buffer: false,
+            strict_ascii: false,
+            drain_lines: false,
+            quiet: false,
+            max_reads: None,
+            max_buffer: DEFAULT_MAX_BUFFER,
+            reads: 0,
+            progress: Progress::default(),
+            transcript: None,
+            #[cfg(feature = "std")]
+            observer: None,
+            #[cfg(feature = "std")]
+            trailing_handler: None,
+            greeting_complete: None,
+            success_token: None,
+            retries: 0,
+            retried: 0,
+            retry_predicate: None,
+        }
+    }
+
+    /// Returns a reference to the protocol.
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    /// Returns a mutable reference to the protocol.
+    pub fn protocol_mut(&mut self) -> &mut P {
+        &mut self.protocol
+    }
+
+    /// See [`Upgrade::discard_greeting`](crate::Upgrade::discard_greeting).
+    pub fn discard_greeting(&mut self, discard: bool) {
+        self.discard_greeting = discard;
+    }
+
+    /// Builder alternative to [`Flow::discard_greeting`].
+    pub fn with_discard_greeting(mut self, discard: bool) -> Self {
+        self.discard_greeting(discard);
+        self
+    }
+
+    /// See [`Upgrade::set_command`](crate::Upgrade::set_command).
+    pub fn set_command(&mut self, command: impl Into<Vec<u8>>) {
+        self.command = Some(command.into());
+    }
+
+    /// Builder alternative to [`Flow::set_command`].
+    pub fn with_command(mut self, command: impl Into<Vec<u8>>) -> Self {
+        self.set_command(command);
+        self
+    }
+
+    /// See [`Upgrade::set_prelude`](crate::Upgrade::set_prelude).
+    pub fn set_prelude(&mut self, commands: Vec<Vec<u8>>) {
+        self.prelude = commands;
+    }
+
+    /// Builder alternative to [`Flow::set_prelude`].
+    pub fn with_prelude(mut self, commands: Vec<Vec<u8>>) -> Self {
+        self.set_prelude(commands);
+        self
+    }
+
+    /// See [`Upgrade::set_skip_command`](crate::Upgrade::set_skip_command).
+    pub fn set_skip_command(&mut self, skip: bool) {
+        self.skip_command = skip;
+    }
+
+    /// Builder alternative to [`Flow::set_skip_command`].
+    pub fn with_skip_command(mut self, skip: bool) -> Self {
+        self.set_skip_command(skip);
+        self
+    }
+
+    /// See [`Upgrade::set_starting_state`](crate::Upgrade::set_starting_state).
+    pub fn set_starting_state(&mut self, state: StartingState) {
+        self.discard_greeting = state == StartingState::DiscardGreeting;
+        self.skip_command = state == StartingState::DiscardResponse;
+    }
+
+    /// Builder alternative to [`Flow::set_starting_state`].
+    pub fn with_starting_state(mut self, state: StartingState) -> Self {
+        self.set_starting_state(state);
+        self
+    }
+
+    /// See [`Upgrade::set_buffer`](crate::Upgrade::set_buffer).
+    pub fn set_buffer(&mut self, bytes: Vec<u8>) {
+        self.bytes = bytes;
+    }
+
+    /// Builder alternative to [`Flow::set_buffer`].
+    pub fn with_buffer(mut self, bytes: Vec<u8>) -> Self {
+        self.set_buffer(bytes);
+        self
+    }
+
+    /// See [`Upgrade::set_max_reads`](crate::Upgrade::set_max_reads).
+    pub fn set_max_reads(&mut self, max: usize) {
+        self.max_reads = Some(max);
+    }
+
+    /// Builder alternative to [`Flow::set_max_reads`].
+    pub fn with_max_reads(mut self, max: usize) -> Self {
+        self.set_max_reads(max);
+        self
+    }
+
+    /// See [`Upgrade::set_max_buffer`](crate::Upgrade::set_max_buffer).
+    pub fn set_max_buffer(&mut self, max: usize) {
+        self.max_buffer = max;
+        self.protocol.set_max_buffer(max);
+    }
+
+    /// Builder alternative to [`Flow::set_max_buffer`].
+    pub fn with_max_buffer(mut self, max: usize) -> Self {
+        self.set_max_buffer(max);
+        self
+    }
+
+    /// See [`Upgrade::set_observer`](crate::Upgrade::set_observer).
+    #[cfg(feature = "std")]
+    pub fn set_observer(&mut self, f: impl FnMut(Event) + Send + 'static) {
+        self.observer = Some(Observer::new(f));
+    }
+
+    /// Builder alternative to [`Flow::set_observer`].
+    #[cfg(feature = "std")]
+    pub fn with_observer(mut self, f: impl FnMut(Event) + Send + 'static) -> Self {
+        self.set_observer(f);
+        self
+    }
+
+    /// See [`Upgrade::set_transcript`](crate::Upgrade::set_transcript).
+    pub fn set_transcript(&mut self, enabled: bool) {
+        self.transcript = enabled.then(Transcript::default);
+    }
+
+    /// Builder alternative to [`Flow::set_transcript`].
+    pub fn with_transcript(mut self, enabled: bool) -> Self {
+        self.set_transcript(enabled);
+        self
+    }
+
+    /// See [`Upgrade::set_strict_buffer`](crate::Upgrade::set_strict_buffer).
+    pub fn set_strict_buffer(&mut self, strict: bool) {
+        self.strict_buffer = strict;
+    }
+
+    /// Builder alternative to [`Flow::set_strict_buffer`].
+    pub fn with_strict_buffer(mut self, strict: bool) -> Self {
+        self.set_strict_buffer(strict);
+        self
+    }
+
+    /// See [`Upgrade::set_trailing_handler`](crate::Upgrade::set_trailing_handler).
+    #[cfg(feature = "std")]
+    pub fn set_trailing_handler(&mut self, f: impl FnMut(&[u8]) + Send + 'static) {
+        self.trailing_handler = Some(TrailingHandler::new(f));
+    }
+
+    /// Builder alternative to [`Flow::set_trailing_handler`].
+    #[cfg(feature = "std")]
+    pub fn with_trailing_handler(mut self, f: impl FnMut(&[u8]) + Send + 'static) -> Self {
+        self.set_trailing_handler(f);
+        self
+    }
+
+    /// See [`Upgrade::set_success_token`](crate::Upgrade::set_success_token).
+    pub fn set_success_token(&mut self, token: impl Into<String>) {
+        self.success_token = Some(token.into());
+    }
+
+    /// Builder alternative to [`Flow::set_success_token`].
+    pub fn with_success_token(mut self, token: impl Into<String>) -> Self {
+        self.set_success_token(token);
+        self
+    }
+
+    /// See [`Upgrade::set_retries`](crate::Upgrade::set_retries).
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    /// Builder alternative to [`Flow::set_retries`].
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.set_retries(retries);
+        self
+    }
+
+    /// See [`Upgrade::set_retry_predicate`](crate::Upgrade::set_retry_predicate).
+    pub fn set_retry_predicate(&mut self, f: impl Fn(&str) -> bool + Send + Sync + 'static) {
+        self.retry_predicate = Some(RetryPredicate::new(f));
+    }
+
+    /// Builder alternative to [`Flow::set_retry_predicate`].
+    pub fn with_retry_predicate(
+        mut self,
+        f: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.set_retry_predicate(f);
+        self
+    }
+
+    /// See [`Upgrade::set_greeting_complete`](crate::Upgrade::set_greeting_complete).
+    pub fn set_greeting_complete(
+        &mut self,
+        f: impl Fn(&[u8]) -> Option<usize> + Send + Sync + 'static,
+    ) {
+        self.greeting_complete = Some(GreetingComplete::new(f));
+    }
+
+    /// Builder alternative to [`Flow::set_greeting_complete`].
+    pub fn with_greeting_complete(
+        mut self,
+        f: impl Fn(&[u8]) -> Option<usize> + Send + Sync + 'static,
+    ) -> Self {
+        self.set_greeting_complete(f);
+        self
+    }
+
+    /// See [`Upgrade::set_strict_ascii`](crate::Upgrade::set_strict_ascii).
+    pub fn set_strict_ascii(&mut self, strict: bool) {
+        self.strict_ascii = strict;
+    }
+
+    /// Builder alternative to [`Flow::set_strict_ascii`].
+    pub fn with_strict_ascii(mut self, strict: bool) -> Self {
+        self.set_strict_ascii(strict);
+        self
+    }
+
+    /// See [`Upgrade::set_drain_lines`](crate::Upgrade::set_drain_lines).
+    pub fn set_drain_lines(&mut self, drain: bool) {
+        self.drain_lines = drain;
+    }
+
+    /// Builder alternative to [`Flow::set_drain_lines`].
+    pub fn with_drain_lines(mut self, drain: bool) -> Self {
+        self.set_drain_lines(drain);
+        self
+    }
+
+    /// See [`Upgrade::set_quiet`](crate::Upgrade::set_quiet).
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+        self.protocol.set_quiet(quiet);
+    }
+
+    /// Builder alternative to [`Flow::set_quiet`].
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.set_quiet(quiet);
+        self
+    }
+
+    /// Returns the STARTTLS command to write, either the overriding
+    /// one or the one built by the protocol.
+    pub fn command(&self) -> &[u8] {
+        match &self.command {
+            Some(command) => command,
+            None => self.protocol.command(),
+        }
+    }
+
+    /// Returns the greeting received from the server.
+    ///
+    /// See [`Upgrade::greeting`](crate::Upgrade::greeting).
+    pub fn greeting(&self) -> Option<&str> {
+        self.greeting.as_deref()
+    }
+
+    /// Returns the STARTTLS response received from the server.
+    ///
+    /// See [`Upgrade::response`](crate::Upgrade::response).
+    pub fn response(&self) -> Option<&str> {
+        self.response.as_deref()
+    }
+
+    /// Returns the bytes received after the STARTTLS response.
+    ///
+    /// See [`Upgrade::remaining`](crate::Upgrade::remaining).
+    pub fn remaining(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the progress of the flow.
+    ///
+    /// See [`Upgrade::resume_progress`](crate::Upgrade::resume_progress).
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// Returns the raw transcript of the bytes exchanged with the
+    /// server.
+    ///
+    /// See [`Upgrade::transcript`](crate::Upgrade::transcript).
+    pub fn transcript(&self) -> &[u8] {
+        match &self.transcript {
+            Some(transcript) => &transcript.bytes,
+            None => &[],
+        }
+    }
+
+    /// Makes the flow progress.
+    ///
+    /// The given input answers the previous step: after
+    /// [`Step::NeedMore`], it contains the bytes read from the
+    /// transport, an empty input meaning that the server closed the
+    /// connection. Otherwise, like for the first call or after
+    /// [`Step::Write`], input is usually empty, but any byte is still
+    /// appended to the buffer.
+    pub fn advance(&mut self, input: &[u8]) -> Step {
+        if let Some(step) = self.receive(input) {
+            return step;
+        }
+
+        self.bytes.extend_from_slice(input);
+        self.run()
+    }
+
+    /// Advances the flow like [`Flow::advance`], taking the first `n`
+    /// bytes of the given read buffer as input.
+    ///
+    /// When the flow has no bytes buffered, which is the case most of
+    /// the time, the read buffer is swapped with the internal one so
+    /// that the read bytes are moved rather than copied. The buffer
+    /// is given back empty either way, ready to be resized for the
+    /// next read.
+    pub fn advance_buffer(&mut self, buffer: &mut Vec<u8>, n: usize) -> Step {
+        let step = match self.receive(&buffer[..n]) {
+            Some(step) => step,
+            None if self.bytes.is_empty() => {
+                buffer.truncate(n);
+                mem::swap(&mut self.bytes, buffer);
+                self.run()
+            }
+            None => {
+                self.bytes.extend_from_slice(&buffer[..n]);
+                self.run()
+            }
+        };
+
+        buffer.clear();
+        step
+    }
+
+    /// Accounts for the given bytes received from the transport,
+    /// before they get buffered.
+    ///
+    /// Returns the step to stop at, if any.
+    fn receive(&mut self, input: &[u8]) -> Option<Step> {
+        if self.need_more {
+            self.need_more = false;
+
+            // reading zero bytes means that the server closed the
+            // connection, which would otherwise make the flow read
+            // forever
+            if input.is_empty() {
+                return Some(Step::Done(Err(UpgradeTlsError::UnexpectedEof)));
+            }
+
+            self.reads += 1;
+        }
+
+        if !input.is_empty() {
+            if let Some(transcript) = &mut self.transcript {
+                transcript.record(Transcript::SERVER, input);
+            }
+        }
+
+        None
+    }
+
+    /// Runs the flow over the buffered bytes, until I/O is needed or
+    /// the flow completes.
+    fn run(&mut self) -> Step {
+        loop {
+            match self.state {
+                State::Begin => match self.starting_state() {
+                    Ok(StartingState::DiscardGreeting) => {
+                        self.state = State::DiscardGreeting;
+                    }
+                    Ok(StartingState::WriteCommand) => return self.next_command(),
+                    Ok(StartingState::DiscardResponse) => {
+                        self.state = State::DiscardResponse;
+                    }
+                    Err(err) => return Step::Done(Err(err)),
+                },
+                State::DiscardGreeting => {
+                    // the greeting may already be buffered, so the
+                    // buffer is checked before reading more bytes
+                    let n = match &self.greeting_complete {
+                        Some(greeting_complete) => greeting_complete.call(&self.bytes),
+                        None => self.protocol.is_greeting_complete(&self.bytes),
+                    };
+
+                    let Some(n) = n else {
+                        return self.need_more();
+                    };
+
+                    // the index may come from a user predicate, which
+                    // cannot be trusted to stay within the buffer
+                    if n == 0 || n > self.bytes.len() {
+                        let len = self.bytes.len();
+                        let reason = format!("greeting end {n} is out of the {len} received bytes");
+                        return Step::Done(Err(UpgradeTlsError::InvalidConfig(reason)));
+                    }
+
+                    self.reads = 0;
+
+                    if let Err(err) = self.check_ascii(n) {
+                        return Step::Done(Err(err));
+                    }
+
+                    if !self.quiet {
+                        discard!("greeting", self.protocol, &self.bytes[..n]);
+                    }
+                    let greeting = String::from_utf8_lossy(&self.bytes[..n]).into_owned();
+                    self.greeting = Some(greeting);
+                    self.notify(Event::GreetingDiscarded { bytes: n });
+                    self.progress.greeting_discarded = true;
+                    self.progress.bytes_consumed += n;
+                    self.bytes.drain(..n);
+
+                    return self.next_command();
+                }
+                State::WritePreludeCommand => {
+                    self.notify(Event::CommandWritten { prelude: true });
+
+                    // bytes received after the previous step are kept,
+                    // since they may be the beginning of the response
+                    self.state = State::ReadPreludeResponse;
+                }
+                State::ReadPreludeResponse => {
+                    let Some(completion) = self.protocol.is_prelude_response_complete(&self.bytes)
+                    else {
+                        return self.need_more();
+                    };
+                    let Completion {
+                        consumed: n,
+                        result,
+                    } = completion;
+
+                    self.reads = 0;
+
+                    if let Err(err) = self.check_ascii(n) {
+                        return Step::Done(Err(err));
+                    }
+
+                    if !self.quiet {
+                        discard!("prelude response", self.protocol, &self.bytes[..n]);
+                    }
+                    self.progress.prelude_commands += 1;
+                    self.progress.bytes_consumed += n;
+                    self.bytes.drain(..n);
+
+                    self.notify(Event::ResponseReceived {
+                        prelude: true,
+                        accepted: result.is_ok(),
+                    });
+
+                    if let Err(err) = result {
+                        return Step::Done(Err(err));
+                    }
+
+                    return self.next_command();
+                }
+                State::WriteStartTlsCommand => {
+                    self.notify(Event::CommandWritten { prelude: false });
+
+                    // bytes received after the previous step are kept,
+                    // since they may be the beginning of the response
+                    self.state = State::DiscardResponse;
+                }
+                State::DiscardResponse => {
+                    let Some(completion) = self.protocol.is_response_complete(&self.bytes) else {
+                        return self.need_more();
+                    };
+                    let Completion {
+                        consumed: n,
+                        result,
+                    } = completion;
+
+                    self.reads = 0;
+
+                    if let Err(err) = self.check_ascii(n) {
+                        return Step::Done(Err(err));
+                    }
+
+                    if !self.quiet {
+                        discard!("response", self.protocol, &self.bytes[..n]);
+                    }
+                    let response = String::from_utf8_lossy(&self.bytes[..n]);
+                    let response = last_line(response.trim_end()).to_owned();
+                    let result = match (&self.success_token, result) {
+                        (Some(token), Ok(()) | Err(UpgradeTlsError::Rejected { .. }))
+                            if !self.protocol.is_binary() =>
+                        {
+                            self.token_result(token, &response)
+                        }
+                        (_, result) => result,
+                    };
+                    self.progress.response = response.clone();
+                    self.response = Some(response);
+                    self.progress.bytes_consumed += n;
+                    self.bytes.drain(..n);
+
+                    self.notify(Event::ResponseReceived {
+                        prelude: false,
+                        accepted: result.is_ok(),
+                    });
+
+                    if let Err(UpgradeTlsError::Rejected { line, .. }) = &result {
+                        if self.retried < self.retries && self.is_transient(line) {
+                            self.retried += 1;
+                            if !self.quiet {
+                                debug!("retry command after transient rejection {line:?}");
+                            }
+                            self.protocol.retry();
+                            return self.next_command();
+                        }
+                    }
+
+                    #[cfg(feature = "std")]
+                    if let Some(handler) = &self.trailing_handler {
+                        handler.call(&self.bytes);
+                    }
+
+                    if result.is_ok() && self.strict_buffer && !self.bytes.is_empty() {
+                        let bytes = self.bytes.clone();
+                        return Step::Done(Err(UpgradeTlsError::TrailingBytes(bytes)));
+                    }
+
+                    return Step::Done(result.map_err(already_secure));
+                }
+            }
+        }
+    }
+
+    /// Builds the result of the given response line, which succeeds
+    /// if its status matches the given token.
+    fn token_result(&self, token: &str, line: &str) -> Result<(), UpgradeTlsError> {
+        let status = self.protocol.status(line.as_bytes());
+
+        if status.eq_ignore_ascii_case(token.as_bytes()) {
+            return Ok(());
+        }
+
+        Err(UpgradeTlsError::Rejected {
+            status: String::from_utf8_lossy(status).into_owned(),
+            line: line.to_owned(),
+        })
+    }
+
+    /// Tells if the given rejection line is transient, in which case
+    /// the STARTTLS command can be written again.
+    fn is_transient(&self, line: &str) -> bool {
+        match &self.retry_predicate {
+            Some(predicate) => predicate.call(line),
+            None => self.protocol.is_transient(line),
+        }
+    }
+
+    /// Returns the step the flow starts with.
+    ///
+    /// See [`Upgrade::starting_state`](crate::Upgrade::starting_state).
+    pub fn starting_state(&self) -> Result<StartingState, UpgradeTlsError> {
+        self.protocol.validate()?;
+
+        if self.skip_command {
+            if !self.prelude.is_empty() || self.protocol.has_prelude() {
+                let reason = "prelude commands cannot be written when skipping the command";
+                return Err(UpgradeTlsError::InvalidConfig(reason.to_owned()));
+            }
+
+            return Ok(StartingState::DiscardResponse);
+        }
+
+        // a line terminator within a command would let another
+        // command be injected before the STARTTLS one
+        let mut commands = self.prelude.iter().chain(&self.command);
+
+        if let Some(command) = commands.find(|c| !is_single_line(c)) {
+            let command = String::from_utf8_lossy(command);
+            let reason = format!("command {command:?} contains line terminators");
+            return Err(UpgradeTlsError::InvalidConfig(reason));
+        }
+
+        if self.discard_greeting {
+            return Ok(StartingState::DiscardGreeting);
+        }
+
+        Ok(StartingState::WriteCommand)
+    }
+
+    /// Resets the flow, so that it can be advanced again from the
+    /// beginning.
+    ///
+    /// See [`Upgrade::reset`](crate::Upgrade::reset).
+    pub fn reset(&mut self) {
+        self.protocol.reset();
+        self.state = State::Begin;
+        self.need_more = false;
+        self.prelude_sent = 0;
+        self.retried = 0;
+        self.bytes.clear();
+        self.greeting = None;
+        self.response = None;
+        self.reads = 0;
+        self.progress = Progress::default();
+        self.transcript = self.transcript.as_ref().map(|_| Transcript::default());
+    }
+
+    /// Notifies the observer of the given event, if any.
+    fn notify(&self, event: Event) {
+        #[cfg(feature = "std")]
+        if let Some(observer) = &self.observer {
+            observer.notify(event);
+        }
+
+        #[cfg(not(feature = "std"))]
+        let _ = event;
+    }
+
+    /// Asks for more bytes to be read into the buffer.
+    ///
+    /// Fails with a timeout if the maximum number of consecutive
+    /// reads has been reached, or with a line too long if the buffer
+    /// reached its maximum size.
+    fn need_more(&mut self) -> Step {
+        if self.max_reads.is_some_and(|max| self.reads >= max) {
+            return Step::Done(Err(UpgradeTlsError::Timeout));
+        }
+
+        if self.drain_lines {
+            if let Err(err) = self.drain_complete_lines() {
+                return Step::Done(Err(err));
+            }
+        }
+
+        // more bytes are only needed when the current step is not
+        // complete, so a full buffer means no terminator came in
+        if self.bytes.len() >= self.max_buffer {
+            return Step::Done(Err(UpgradeTlsError::LineTooLong));
+        }
+
+        self.need_more = true;
+        Step::NeedMore
+    }
+
+    /// Drains the complete lines of the buffer, which did not
+    /// complete the current step.
+    fn drain_complete_lines(&mut self) -> Result<(), UpgradeTlsError> {
+        let len = self.bytes.len();
+        let offset = self.protocol.drain_offset(&self.bytes);
+
+        let Some(n) = offset.filter(|n| (1..=len).contains(n)) else {
+            return Ok(());
+        };
+
+        self.check_ascii(n)?;
+
+        if !self.quiet {
+            discard!("lines", self.protocol, &self.bytes[..n]);
+        }
+        self.progress.bytes_consumed += n;
+        self.bytes.drain(..n);
+        self.protocol.drained(n);
+
+        Ok(())
+    }
+
+    /// Checks that the first `n` bytes of the buffer are ASCII, when
+    /// the strict ASCII mode is enabled.
+    fn check_ascii(&self, n: usize) -> Result<(), UpgradeTlsError> {
+        let bytes = &self.bytes[..n];
+
+        if self.strict_ascii && !bytes.is_ascii() {
+            return Err(UpgradeTlsError::NonAscii(bytes.to_vec()));
+        }
+
+        Ok(())
+    }
+
+    /// Hands over the next prelude command if any, otherwise the
+    /// STARTTLS command.
+    fn next_command(&mut self) -> Step {
+        // queued commands are written first, then the ones required
+        // by the protocol
+        let command = match self.prelude.get(self.prelude_sent) {
+            Some(command) => {
+                self.prelude_sent += 1;
+                Some(command.clone())
+            }
+            None => match self.protocol.prelude() {
+                Ok(command) => command,
+                Err(err) => return Step::Done(Err(err)),
+            },
+        };
+
+        if let Some(command) = command {
+            if !self.quiet {
+                debug!(
+                    "enqueue prelude command {:?}",
+                    String::from_utf8_lossy(&command)
+                );
+            }
+
+            if let Some(transcript) = &mut self.transcript {
+                transcript.record(Transcript::CLIENT, &command);
+            }
+
+            self.state = State::WritePreludeCommand;
+            return Step::Write(command);
+        }
+
+        let command = self.command().to_vec();
+
+        if !self.quiet {
+            debug!("enqueue command {:?}", String::from_utf8_lossy(&command));
+        }
+
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record(Transcript::CLIENT, &command);
+        }
+
+        self.state = State::WriteStartTlsCommand;
+        Step::Write(command)
+    }
+}
+
+/// The raw transcript of the bytes exchanged with the server.
+///
+/// Every line is prefixed by its direction: `C: ` for bytes written
+/// by the client, `S: ` for bytes read from the server.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Transcript {
+    bytes: Vec<u8>,
+    prefix: Option<&'static [u8]>,
+    line_start: bool,
+}
+
+impl Transcript {
+    /// The prefix of bytes written by the client.
+    const CLIENT: &'static [u8] = b"C: ";
+
+    /// The prefix of bytes read from the server.
+    const SERVER: &'static [u8] = b"S: ";
+
+    /// Records the given bytes, prefixing every new line.
+    fn record(&mut self, prefix: &'static [u8], bytes: &[u8]) {
+        // a direction change always starts a new line
+        if self.prefix != Some(prefix) {
+            if !self.bytes.is_empty() && !self.line_start {
+                self.bytes.push(b'\n');
+            }
+
+            self.prefix = Some(prefix);
+            self.line_start = true;
+        }
+
+        for line in bytes.split_inclusive(|b| *b == b'\n') {
+            if self.line_start {
+                self.bytes.extend_from_slice(prefix);
+            }
+
+            self.bytes.extend_from_slice(line);
+            self.line_start = line.ends_with(b"\n");
+        }
+    }
+}
+
+/// The handler called with the bytes received after the STARTTLS
+/// response.
+///
+/// The handler is shared between clones of the coroutine.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub(crate) struct TrailingHandler(Arc<Mutex<TrailingFn>>);
+
+/// The function wrapped by a [`TrailingHandler`].
+#[cfg(feature = "std")]
+type TrailingFn = dyn FnMut(&[u8]) + Send;
+
+#[cfg(feature = "std")]
+impl TrailingHandler {
+    /// Creates a new handler from the given function.
+    pub(crate) fn new(f: impl FnMut(&[u8]) + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(f)))
+    }
+
+    /// Calls the handler with the given trailing bytes.
+    pub(crate) fn call(&self, bytes: &[u8]) {
+        let mut f = match self.0.lock() {
+            Ok(f) => f,
+            Err(err) => err.into_inner(),
+        };
+
+        f(bytes)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for TrailingHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TrailingHandler")
+    }
+}
+
+/// The predicate overriding the greeting completion check of the
+/// protocol.
+///
+/// The predicate is shared between clones of the coroutine.
+#[derive(Clone)]
+pub(crate) struct GreetingComplete(Arc<GreetingCompleteFn>);
+
+/// The function wrapped by a [`GreetingComplete`].
+type GreetingCompleteFn = dyn Fn(&[u8]) -> Option<usize> + Send + Sync;
+
+impl GreetingComplete {
+    /// Creates a new predicate from the given function.
+    pub(crate) fn new(f: impl Fn(&[u8]) -> Option<usize> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Calls the predicate with the given buffered bytes.
+    pub(crate) fn call(&self, bytes: &[u8]) -> Option<usize> {
+        (self.0)(bytes)
+    }
+}
+
+impl fmt::Debug for GreetingComplete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("GreetingComplete")
+    }
+}
+
+/// The predicate telling if a rejection of the STARTTLS command is
+/// transient.
+///
+/// The predicate is shared between clones of the coroutine.
+#[derive(Clone)]
+pub(crate) struct RetryPredicate(Arc<RetryPredicateFn>);
+
+/// The function wrapped by a [`RetryPredicate`].
+type RetryPredicateFn = dyn Fn(&str) -> bool + Send + Sync;
+
+impl RetryPredicate {
+    /// Creates a new predicate from the given function.
+    pub(crate) fn new(f: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Calls the predicate with the given rejection line.
+    pub(crate) fn call(&self, line: &str) -> bool {
+        (self.0)(line)
+    }
+}
+
+impl fmt::Debug for RetryPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RetryPredicate")
+    }
+}
+
+/// Returns `true` if the given rejection line, with the given
+/// status, is transient by default.
+///
+/// Rejections are considered transient when they hold the IMAP
+/// `[UNAVAILABLE]` response code (RFC 5530), or when their status is
+/// a transient negative reply code, like `454` for SMTP. The status
+/// is the one parsed by the protocol, so that an IMAP tag like `400`
+/// is not mistaken for a reply code.
+pub(crate) fn is_transient(status: &[u8], line: &str) -> bool {
+    let transient_code =
+        status.len() == 3 && status.starts_with(b"4") && status.iter().all(u8::is_ascii_digit);
+
+    transient_code || line.to_ascii_uppercase().contains("[UNAVAILABLE]")
+}
+
+/// Turns the given rejection into [`UpgradeTlsError::AlreadySecure`]
+/// if its line tells that TLS is already active.
+///
+/// Servers answer a STARTTLS command sent over a secure stream with
+/// such rejections, like `NGC6543 BAD TLS already active` for IMAP
+/// or `554 5.5.1 Error: TLS already active` for SMTP.
+fn already_secure(err: UpgradeTlsError) -> UpgradeTlsError {
+    match err {
+        UpgradeTlsError::Rejected { line, .. } if is_already_secure(&line) => {
+            UpgradeTlsError::AlreadySecure(line)
+        }
+        err => err,
+    }
+}
+
+/// Returns `true` if the given rejection line tells that TLS is
+/// already active.
+fn is_already_secure(line: &str) -> bool {
+    let line = line.to_ascii_lowercase();
+
+    line.contains("already")
+        && ["tls", "ssl", "secure", "encrypt"]
+            .iter()
+            .any(|word| line.contains(word))
+}
+
+/// Returns `true` if the given command does not contain any line
+/// terminator, except the trailing one.
+fn is_single_line(command: &[u8]) -> bool {
+    let command = command.strip_suffix(b"\n").unwrap_or(command);
+    let command = command.strip_suffix(b"\r").unwrap_or(command);
+    !command.iter().any(|b| *b == b'\r' || *b == b'\n')
+}
+
+/// Returns the last line of the given text.
+fn last_line(text: &str) -> &str {
+    text.rsplit('\n').next().unwrap_or(text)
+}
@@ -0,0 +1,105 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the
+//! ManageSieve protocol.
+
+use alloc::{borrow::ToOwned, string::String};
+
+use memchr::memchr;
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{upgrade::drain_lines_offset, Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain ManageSieve service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 4190;
+
+/// The STARTTLS command written to the server.
+pub const STARTTLS_COMMAND: &[u8] = b"STARTTLS\r\n";
+
+/// The STARTTLS coroutine that upgrades a plain ManageSieve (TCP)
+/// stream to a secure one.
+///
+/// In ManageSieve, the greeting consists of the capability lines
+/// terminated by an `OK` line.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Sieve>;
+
+/// The ManageSieve flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug, Default)]
+pub struct Sieve;
+
+impl StartTls for Sieve {
+    fn command(&self) -> &[u8] {
+        STARTTLS_COMMAND
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        find_status_line(bytes).map(|(_, n)| n + 1)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let (start, end) = find_status_line(bytes)?;
+        let line = &bytes[start..=end];
+
+        let status = line
+            .split(u8::is_ascii_whitespace)
+            .next()
+            .unwrap_or_default();
+
+        if status.eq_ignore_ascii_case(b"OK") {
+            return Some(Completion::new(end + 1, Ok(())));
+        }
+
+        let line = String::from_utf8_lossy(line).trim_end().to_owned();
+
+        // the server closes the connection right after a BYE
+        let err = if status.eq_ignore_ascii_case(b"BYE") {
+            UpgradeTlsError::ServerClosing(line)
+        } else {
+            UpgradeTlsError::Rejected {
+                status: String::from_utf8_lossy(status).into_owned(),
+                line,
+            }
+        };
+
+        Some(Completion::new(end + 1, Err(err)))
+    }
+
+    /// Lines before the status line are skipped one by one, so
+    /// complete lines are never needed again.
+    fn drain_offset(&self, bytes: &[u8]) -> Option<usize> {
+        drain_lines_offset(bytes)
+    }
+}
+
+/// Finds the first ManageSieve status line.
+///
+/// A status line is a line starting at column zero with either `OK`,
+/// `NO` or `BYE`, case-insensitively. Any line before it (like
+/// capabilities) is skipped. Returns the index of the first byte of
+/// the status line and the index of the `\n` terminating it.
+fn find_status_line(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 0;
+
+    while let Some(n) = memchr(b'\n', &bytes[start..]) {
+        let end = start + n;
+        let line = &bytes[start..end];
+
+        if [b"OK".as_slice(), b"NO", b"BYE"]
+            .iter()
+            .any(|status| starts_with_ignore_case(line, status))
+        {
+            return Some((start, end));
+        }
+
+        start = end + 1;
+    }
+
+    None
+}
+
+/// Returns `true` if the given line starts with the given status,
+/// case-insensitively.
+fn starts_with_ignore_case(line: &[u8], status: &[u8]) -> bool {
+    line.get(..status.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(status))
+}
@@ -0,0 +1,383 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the SMTP
+//! protocol.
+
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+
+#[cfg(feature = "io-stream")]
+use io_stream::{
+    coroutines::{Read, Write},
+    Io,
+};
+use memchr::memchr;
+
+use crate::{
+    upgrade::{drain_lines_offset, reply_result},
+    Completion, StartTls, UpgradeTlsError,
+};
+#[cfg(feature = "io-stream")]
+use crate::{
+    upgrade::{fill, flush},
+    Upgrade,
+};
+
+/// The default port of the SMTP submission service, where clients
+/// are expected to upgrade the stream via STARTTLS. Relays use the
+/// port 25 instead.
+pub const DEFAULT_STARTTLS_PORT: u16 = 587;
+
+/// The STARTTLS command written to the server.
+pub const STARTTLS_COMMAND: &[u8] = b"STARTTLS\r\n";
+
+/// The client domain sent with EHLO by default, commonly used when
+/// the local host name is unknown, see
+/// [`UpgradeTls::set_ehlo_domain`].
+pub const DEFAULT_EHLO_DOMAIN: &str = "localhost";
+
+/// The STARTTLS coroutine that upgrades a plain SMTP (TCP) stream to
+/// a secure one.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Smtp>;
+
+/// The SMTP flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug)]
+pub struct Smtp {
+    verb: &'static str,
+    domain: String,
+    hello: bool,
+    hello_sent: bool,
+    tls_required: bool,
+}
+
+impl Smtp {
+    /// Creates a new protocol sending the given hello verb before
+    /// the STARTTLS command, like `LHLO` for LMTP.
+    pub(crate) fn from_verb(verb: &'static str) -> Self {
+        Self {
+            verb,
+            domain: DEFAULT_EHLO_DOMAIN.to_owned(),
+            hello: true,
+            hello_sent: false,
+            tls_required: false,
+        }
+    }
+
+    /// Tells the coroutine to send EHLO with the given client domain
+    /// before the STARTTLS command.
+    ///
+    /// See [`Upgrade::set_ehlo_domain`].
+    pub fn set_ehlo(&mut self, domain: impl Into<String>) {
+        self.domain = domain.into();
+        self.hello = true;
+    }
+
+    /// Builder alternative to [`Smtp::set_ehlo`].
+    pub fn with_ehlo(mut self, domain: impl Into<String>) -> Self {
+        self.set_ehlo(domain);
+        self
+    }
+
+    /// Tells the coroutine to send EHLO before the STARTTLS command.
+    ///
+    /// See [`Upgrade::set_ehlo_enabled`].
+    pub fn set_ehlo_enabled(&mut self, enabled: bool) {
+        self.hello = enabled;
+    }
+
+    /// Builder alternative to [`Smtp::set_ehlo_enabled`].
+    pub fn with_ehlo_enabled(mut self, enabled: bool) -> Self {
+        self.set_ehlo_enabled(enabled);
+        self
+    }
+}
+
+impl Default for Smtp {
+    fn default() -> Self {
+        Self::from_verb("EHLO")
+    }
+}
+
+impl StartTls for Smtp {
+    fn command(&self) -> &[u8] {
+        STARTTLS_COMMAND
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        find_reply_end(bytes).map(|n| n + 1)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let n = find_reply_end(bytes)?;
+        Some(Completion::new(n + 1, reply_result(&bytes[..=n], b"220")))
+    }
+
+    fn prelude(&mut self) -> Result<Option<Vec<u8>>, UpgradeTlsError> {
+        if !self.hello || self.hello_sent {
+            return Ok(None);
+        }
+
+        self.hello_sent = true;
+        Ok(Some(
+            format!("{} {}\r\n", self.verb, self.domain).into_bytes(),
+        ))
+    }
+
+    fn validate(&self) -> Result<(), UpgradeTlsError> {
+        if !self.hello {
+            return Ok(());
+        }
+
+        validate_domain(self.verb, &self.domain)
+    }
+
+    fn has_prelude(&self) -> bool {
+        self.hello && !self.hello_sent
+    }
+
+    /// Prelude commands succeed with any positive completion reply
+    /// (`2xx`), like `250` for `EHLO`.
+    fn is_prelude_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let n = find_reply_end(bytes)?;
+        let reply = &bytes[..=n];
+
+        if is_starttls_required(reply) {
+            self.tls_required = true;
+        }
+
+        Some(Completion::new(n + 1, reply_result(reply, b"2")))
+    }
+
+    /// Every line of a multiline reply holds its code, so complete
+    /// lines are never needed again.
+    fn drain_offset(&self, bytes: &[u8]) -> Option<usize> {
+        drain_lines_offset(bytes)
+    }
+
+    /// Only the `530` reply to a command sent before STARTTLS tells
+    /// that TLS is mandatory, nothing tells the opposite.
+    fn tls_required(&self) -> Option<bool> {
+        self.tls_required.then_some(true)
+    }
+
+    fn reset(&mut self) {
+        self.hello_sent = false;
+        self.tls_required = false;
+    }
+}
+
+#[cfg(feature = "io-stream")]
+impl Upgrade<Smtp> {
+    /// Tells the coroutine to send EHLO with the given client domain
+    /// before the STARTTLS command.
+    ///
+    /// Most servers only accept STARTTLS after EHLO, and many of them
+    /// rate the domain as part of their anti-spam heuristics, even
+    /// before TLS: bare IP addresses should be sent as address
+    /// literals (like `[192.0.2.1]`), and a fully qualified domain is
+    /// preferred. Defaults to [`DEFAULT_EHLO_DOMAIN`], the common
+    /// fallback when the local host name is unknown. A domain that is
+    /// empty or contains spaces or line terminators makes the
+    /// coroutine fail with [`UpgradeTlsError::InvalidConfig`] before
+    /// writing anything, since it would let another command be
+    /// injected before the STARTTLS one.
+    ///
+    /// See also [`UpgradeTls::with_ehlo_domain`] for the builder
+    /// alternative.
+    pub fn set_ehlo_domain(&mut self, domain: impl Into<String>) {
+        self.protocol_mut().set_ehlo(domain);
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_ehlo_domain`].
+    pub fn with_ehlo_domain(mut self, domain: impl Into<String>) -> Self {
+        self.set_ehlo_domain(domain);
+        self
+    }
+
+    /// Tells the coroutine to send EHLO before the STARTTLS command.
+    ///
+    /// Enabled by default. This may be disabled when EHLO has already
+    /// been sent by another code path, or is queued via
+    /// [`Upgrade::set_prelude`], so that it is not sent twice. It
+    /// also needs to be disabled when skipping the STARTTLS command,
+    /// see [`Upgrade::set_skip_command`].
+    ///
+    /// See also [`UpgradeTls::with_ehlo_enabled`] for the builder
+    /// alternative.
+    pub fn set_ehlo_enabled(&mut self, enabled: bool) {
+        self.protocol_mut().set_ehlo_enabled(enabled);
+    }
+
+    /// Builder alternative to [`UpgradeTls::set_ehlo_enabled`].
+    pub fn with_ehlo_enabled(mut self, enabled: bool) -> Self {
+        self.set_ehlo_enabled(enabled);
+        self
+    }
+}
+
+/// Internal state of the [`Ehlo`] flow.
+#[cfg(feature = "io-stream")]
+#[derive(Clone, Debug)]
+enum EhloState {
+    /// The domain is not valid, the coroutine fails without writing
+    /// anything.
+    Invalid(UpgradeTlsError),
+    /// The EHLO command needs to be written.
+    WriteCommand(Write),
+    /// The EHLO response needs to be read.
+    ReadResponse,
+}
+
+/// The coroutine that sends the EHLO command and collects the
+/// capabilities advertised by the server.
+///
+/// Capabilities change once the stream is upgraded, so SMTP clients
+/// are required to send EHLO again right after the TLS handshake.
+/// This coroutine is meant to be resumed against the secure stream.
+#[cfg(feature = "io-stream")]
+#[derive(Clone, Debug)]
+pub struct Ehlo {
+    state: EhloState,
+    read: Read,
+    bytes: Vec<u8>,
+}
+
+#[cfg(feature = "io-stream")]
+impl Ehlo {
+    /// Creates a new EHLO coroutine for the given client domain.
+    pub fn new(domain: impl AsRef<str>) -> Self {
+        Self::from_verb("EHLO", domain.as_ref())
+    }
+
+    /// Creates a new LHLO coroutine for the given client domain.
+    ///
+    /// LMTP replaces EHLO by LHLO, while the response remains the
+    /// same.
+    pub fn lhlo(domain: impl AsRef<str>) -> Self {
+        Self::from_verb("LHLO", domain.as_ref())
+    }
+
+    /// Creates a new coroutine sending the given hello verb.
+    ///
+    /// The domain is checked like the one of [`Smtp::set_ehlo`]: an
+    /// invalid domain makes the coroutine fail on its first resume.
+    fn from_verb(verb: &str, domain: &str) -> Self {
+        let state = match validate_domain(verb, domain) {
+            Ok(()) => {
+                let command = format!("{verb} {domain}\r\n");
+                debug!("enqueue command {command:?}");
+                EhloState::WriteCommand(Write::new(command.into_bytes()))
+            }
+            Err(err) => EhloState::Invalid(err),
+        };
+
+        Self {
+            state,
+            read: Read::default(),
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Makes the coroutine progress.
+    ///
+    /// The coroutine returns `Err(io)` as long as it needs I/O to be
+    /// processed. Once the EHLO response has been received, it
+    /// returns `Ok(Ok(capabilities))` if the server accepted the
+    /// command, otherwise `Ok(Err(err))`. The first line of the
+    /// response, which greets the client, is not a capability. The
+    /// coroutine also fails if the server closed the connection, or
+    /// if the response exceeds [`DEFAULT_MAX_BUFFER`].
+    ///
+    /// [`DEFAULT_MAX_BUFFER`]: crate::DEFAULT_MAX_BUFFER
+    pub fn resume(
+        &mut self,
+        mut io: Option<Io>,
+    ) -> Result<Result<Vec<String>, UpgradeTlsError>, Io> {
+        loop {
+            match &mut self.state {
+                EhloState::Invalid(err) => break Ok(Err(err.clone())),
+                EhloState::WriteCommand(write) => match flush(write, io.take())? {
+                    Ok(true) => self.state = EhloState::ReadResponse,
+                    Ok(false) => continue,
+                    Err(err) => break Ok(Err(err)),
+                },
+                EhloState::ReadResponse => {
+                    let Some(n) = find_reply_end(&self.bytes) else {
+                        if let Err(err) = fill(&mut self.read, &mut self.bytes, io.take())? {
+                            break Ok(Err(err));
+                        }
+                        continue;
+                    };
+
+                    let reply = &self.bytes[..=n];
+                    debug!("receive response {:?}", String::from_utf8_lossy(reply));
+
+                    if let Err(err) = reply_result(reply, b"250") {
+                        break Ok(Err(err));
+                    }
+
+                    break Ok(Ok(parse_capabilities(reply)));
+                }
+            }
+        }
+    }
+}
+
+/// Checks that the given hello domain can be written as is.
+///
+/// A domain containing line terminators would let another command
+/// be injected before the STARTTLS one, while spaces would split it
+/// into several arguments.
+fn validate_domain(verb: &str, domain: &str) -> Result<(), UpgradeTlsError> {
+    if domain.is_empty()
+        || domain.contains(|c: char| c.is_ascii_whitespace() || c.is_ascii_control())
+    {
+        let reason = format!("{verb} domain {domain:?} is empty or contains spaces");
+        return Err(UpgradeTlsError::InvalidConfig(reason));
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if the given reply tells that STARTTLS needs to be
+/// issued first, like `530 5.7.0 Must issue a STARTTLS command first`
+/// defined in RFC 3207.
+fn is_starttls_required(reply: &[u8]) -> bool {
+    let reply = String::from_utf8_lossy(reply).to_ascii_uppercase();
+    reply.starts_with("530") && reply.contains("STARTTLS")
+}
+
+/// Finds the end of a (possibly multiline) SMTP reply.
+///
+/// Every line of a multiline reply starts with a 3-digit code
+/// followed by a hyphen (`250-`), except the last one where the code
+/// is followed by a space (`250 `). Returns the index of the `\n`
+/// terminating the last line.
+pub(crate) fn find_reply_end(bytes: &[u8]) -> Option<usize> {
+    let mut start = 0;
+
+    while let Some(n) = memchr(b'\n', &bytes[start..]) {
+        let end = start + n;
+
+        if bytes.get(start + 3) != Some(&b'-') {
+            return Some(end);
+        }
+
+        start = end + 1;
+    }
+
+    None
+}
+
+/// Parses the capabilities of the given EHLO reply.
+///
+/// Every line but the first one advertises a capability, located
+/// right after the code and its separator (`250-` or `250 `).
+#[cfg(feature = "io-stream")]
+fn parse_capabilities(reply: &[u8]) -> Vec<String> {
+    reply
+        .split(|byte| *byte == b'\n')
+        .skip(1)
+        .filter_map(|line| line.get(4..))
+        .map(|capability| String::from_utf8_lossy(capability).trim().to_owned())
+        .filter(|capability| !capability.is_empty())
+        .collect()
+}
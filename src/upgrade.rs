@@ -0,0 +1,1630 @@
+//! Module dedicated to the generic [`Upgrade`] coroutine and its
+//! [`StartTls`] protocol trait.
+
+#[cfg(any(feature = "ftp", feature = "smtp"))]
+use alloc::borrow::ToOwned;
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+#[cfg(feature = "io-stream")]
+use io_stream::{
+    coroutines::{Read, Write},
+    Io,
+};
+
+#[cfg(feature = "io-stream")]
+use crate::sans_io::{Flow, Step};
+#[cfg(feature = "std")]
+use crate::Event;
+use crate::{sans_io::is_transient, UpgradeTlsError};
+
+/// The protocol-specific part of the STARTTLS flow.
+///
+/// The [`Upgrade`] coroutine drives the flow shared by all protocols
+/// (discard the greeting, write the STARTTLS command, discard the
+/// response), and relies on this trait for everything that differs
+/// from one protocol to another.
+///
+/// Completion checks receive all the bytes received so far for the
+/// current step. They return `None` as long as more bytes are needed,
+/// otherwise the number of bytes consumed by the step, along with the
+/// response result as a [`Completion`]. No framing is assumed: line
+/// protocols look for line terminators, while binary ones (like
+/// LDAP, MySQL or Postgres) decode their own packets.
+pub trait StartTls {
+    /// Returns the STARTTLS command to write.
+    fn command(&self) -> &[u8];
+
+    /// Checks if the given bytes contain the whole greeting.
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize>;
+
+    /// Checks if the given bytes contain the whole STARTTLS response.
+    ///
+    /// The result tells if the server accepted the command or not.
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion>;
+
+    /// Returns the next command to write before the STARTTLS one, if
+    /// any.
+    ///
+    /// This hook is called right before writing any command: after
+    /// the greeting, then after every prelude response, until it
+    /// returns `None`. It allows protocols
+    /// to negotiate things before STARTTLS, like capabilities.
+    fn prelude(&mut self) -> Result<Option<Vec<u8>>, UpgradeTlsError> {
+        Ok(None)
+    }
+
+    /// Checks the configuration of the protocol.
+    ///
+    /// This is called before the coroutine starts, along with the
+    /// other configuration checks of [`Upgrade::starting_state`], so
+    /// that an invalid configuration (like an IMAP tag containing
+    /// spaces) fails before anything is written. Defaults to `Ok`.
+    fn validate(&self) -> Result<(), UpgradeTlsError> {
+        Ok(())
+    }
+
+    /// Tells if [`StartTls::prelude`] is going to return commands.
+    ///
+    /// This is used to validate the configuration of the coroutine
+    /// before it starts, since prelude commands cannot be written
+    /// when the STARTTLS command is skipped.
+    fn has_prelude(&self) -> bool {
+        false
+    }
+
+    /// Checks if the given bytes contain the whole response of the
+    /// last prelude command.
+    ///
+    /// Defaults to [`StartTls::is_response_complete`], which fits
+    /// protocols sharing the same success status for all commands.
+    fn is_prelude_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        self.is_response_complete(bytes)
+    }
+
+    /// Returns the status token of the given response line, like
+    /// `OK` for IMAP or `220` for SMTP.
+    ///
+    /// This is used to check the response against the token set via
+    /// [`Upgrade::set_success_token`]. Defaults to the first
+    /// whitespace-separated token of the line, which fits protocols
+    /// starting their responses with a status code.
+    fn status<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        line.split(u8::is_ascii_whitespace)
+            .next()
+            .unwrap_or_default()
+    }
+
+    /// Tells if the protocol is a binary one, like LDAP, MySQL or
+    /// Postgres.
+    ///
+    /// Binary responses have no status token, so the token set via
+    /// [`Upgrade::set_success_token`] does not apply to them.
+    /// Defaults to `false`.
+    fn is_binary(&self) -> bool {
+        false
+    }
+
+    /// Returns the number of leading bytes of the buffer that can be
+    /// drained before the current step completes, if any.
+    ///
+    /// This is called when [`Upgrade::set_drain_lines`] is enabled
+    /// and more bytes are needed. Only the bytes the completion checks
+    /// do not need anymore can be drained: the framing of the
+    /// protocol needs to be taken into account, like IMAP literals
+    /// spanning several lines. Defaults to `None`, which disables
+    /// draining.
+    fn drain_offset(&self, bytes: &[u8]) -> Option<usize> {
+        let _ = bytes;
+        None
+    }
+
+    /// Tells the protocol that the first `n` bytes of the buffer have
+    /// been drained before the current step completed.
+    ///
+    /// This only happens when [`Upgrade::set_drain_lines`] is
+    /// enabled, and allows protocols tracking positions in the buffer
+    /// to adjust them.
+    fn drained(&mut self, n: usize) {
+        let _ = n;
+    }
+
+    /// Tells the protocol to skip its own logs, if any.
+    ///
+    /// This is called by [`Upgrade::set_quiet`], protocols logging
+    /// data received from the server need to honor it.
+    fn set_quiet(&mut self, quiet: bool) {
+        let _ = quiet;
+    }
+
+    /// Tells the protocol the maximum size of the buffer.
+    ///
+    /// This is called by [`Upgrade::set_max_buffer`], and allows
+    /// protocols to reject lengths announced by the server that could
+    /// never fit in the buffer, like IMAP literals.
+    fn set_max_buffer(&mut self, max: usize) {
+        let _ = max;
+    }
+
+    /// Resets the state collected during a previous flow, if any.
+    fn reset(&mut self) {}
+
+    /// Returns the name of the protocol, used by logs.
+    ///
+    /// Defaults to the name of the implementing type.
+    fn name(&self) -> &'static str {
+        let name = core::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// Returns the tag of the current command, if the protocol uses
+    /// tags.
+    fn tag(&self) -> Option<&str> {
+        None
+    }
+
+    /// Tells if the server requires the stream to be upgraded, from
+    /// what has been received so far.
+    ///
+    /// Returns `Some(true)` once the server told that TLS is
+    /// mandatory, `Some(false)` if it told that plain connections
+    /// are usable, and `None` if it did not tell anything. Defaults
+    /// to `None`.
+    fn tls_required(&self) -> Option<bool> {
+        None
+    }
+
+    /// Returns the capabilities advertised by the server, from what
+    /// has been received so far.
+    ///
+    /// Defaults to an empty slice, for protocols which do not
+    /// collect capabilities.
+    fn capabilities(&self) -> &[String] {
+        &[]
+    }
+
+    /// Tells if the given rejection line of the STARTTLS command is
+    /// transient, see [`Upgrade::set_retries`].
+    ///
+    /// Defaults to rejections holding the IMAP `[UNAVAILABLE]`
+    /// response code (RFC 5530), or a transient negative reply code
+    /// like `454` for SMTP, read via [`StartTls::status`].
+    fn is_transient(&self, line: &str) -> bool {
+        is_transient(self.status(line.as_bytes()), line)
+    }
+
+    /// Prepares the STARTTLS command to be written again after a
+    /// transient rejection, see [`Upgrade::set_retries`].
+    ///
+    /// This is called right before [`StartTls::prelude`], which lets
+    /// protocols rebuild their command, like IMAP giving it a fresh
+    /// tag. Defaults to a no-op.
+    fn retry(&mut self) {}
+}
+
+/// Forwards the protocol to the boxed one, which allows protocols to
+/// be selected at runtime, see [`crate::Protocol::upgrade`].
+impl<P: StartTls + ?Sized> StartTls for Box<P> {
+    fn command(&self) -> &[u8] {
+        (**self).command()
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        (**self).is_greeting_complete(bytes)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        (**self).is_response_complete(bytes)
+    }
+
+    fn prelude(&mut self) -> Result<Option<Vec<u8>>, UpgradeTlsError> {
+        (**self).prelude()
+    }
+
+    fn validate(&self) -> Result<(), UpgradeTlsError> {
+        (**self).validate()
+    }
+
+    fn has_prelude(&self) -> bool {
+        (**self).has_prelude()
+    }
+
+    fn is_prelude_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        (**self).is_prelude_response_complete(bytes)
+    }
+
+    fn status<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        (**self).status(line)
+    }
+
+    fn is_binary(&self) -> bool {
+        (**self).is_binary()
+    }
+
+    fn drain_offset(&self, bytes: &[u8]) -> Option<usize> {
+        (**self).drain_offset(bytes)
+    }
+
+    fn drained(&mut self, n: usize) {
+        (**self).drained(n)
+    }
+
+    fn set_quiet(&mut self, quiet: bool) {
+        (**self).set_quiet(quiet)
+    }
+
+    fn set_max_buffer(&mut self, max: usize) {
+        (**self).set_max_buffer(max)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn tag(&self) -> Option<&str> {
+        (**self).tag()
+    }
+
+    fn tls_required(&self) -> Option<bool> {
+        (**self).tls_required()
+    }
+
+    fn capabilities(&self) -> &[String] {
+        (**self).capabilities()
+    }
+
+    fn is_transient(&self, line: &str) -> bool {
+        (**self).is_transient(line)
+    }
+
+    fn retry(&mut self) {
+        (**self).retry()
+    }
+}
+
+/// The outcome of a [`StartTls`] response completion check.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Completion {
+    /// The number of bytes consumed by the response.
+    pub consumed: usize,
+    /// Tells if the server accepted the command or not.
+    pub result: Result<(), UpgradeTlsError>,
+}
+
+impl Completion {
+    /// Creates a new completion from the number of consumed bytes
+    /// and the response result.
+    pub fn new(consumed: usize, result: Result<(), UpgradeTlsError>) -> Self {
+        Self { consumed, result }
+    }
+}
+
+/// The default maximum size of the buffer, in bytes.
+///
+/// See [`Upgrade::set_max_buffer`].
+pub const DEFAULT_MAX_BUFFER: usize = 64 * 1024;
+
+/// The step the [`Upgrade`] flow starts with.
+///
+/// The starting step depends on [`Upgrade::discard_greeting`] and
+/// [`Upgrade::set_skip_command`]:
+///
+/// | discard greeting | skip command | starting step       |
+/// |------------------|--------------|---------------------|
+/// | `false`          | `false`      | [`WriteCommand`]    |
+/// | `true`           | `false`      | [`DiscardGreeting`] |
+/// | any              | `true`       | [`DiscardResponse`] |
+///
+/// Skipping the command is not compatible with prelude commands,
+/// whether they are queued or required by the protocol.
+///
+/// See [`Upgrade::starting_state`].
+///
+/// [`WriteCommand`]: StartingState::WriteCommand
+/// [`DiscardGreeting`]: StartingState::DiscardGreeting
+/// [`DiscardResponse`]: StartingState::DiscardResponse
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StartingState {
+    /// The flow starts by discarding the greeting.
+    DiscardGreeting,
+    /// The flow starts by writing the first command, either a
+    /// prelude or the STARTTLS one.
+    WriteCommand,
+    /// The flow starts by discarding the STARTTLS response.
+    DiscardResponse,
+}
+
+/// The progress of a completed [`Upgrade`] flow.
+///
+/// See [`Upgrade::resume_progress`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Progress {
+    /// Whether the greeting has been discarded by the coroutine.
+    pub greeting_discarded: bool,
+    /// The number of prelude commands written before the STARTTLS
+    /// one.
+    pub prelude_commands: usize,
+    /// The line completing the STARTTLS response, lossy-decoded
+    /// from UTF-8.
+    pub response: String,
+    /// The number of bytes consumed by the flow, trailing bytes
+    /// excluded.
+    pub bytes_consumed: usize,
+}
+
+/// The report of a successful [`Upgrade`] flow.
+///
+/// See [`Upgrade::resume_report`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CompletionReport {
+    /// The greeting received from the server, see
+    /// [`Upgrade::greeting`].
+    pub greeting: Option<String>,
+    /// The line completing the STARTTLS response, see
+    /// [`Upgrade::response`].
+    pub response: String,
+    /// The status of the STARTTLS response, like `OK` for IMAP or
+    /// `220` for SMTP, see [`StartTls::status`]. It is
+    /// [`Status::None`] for binary protocols, see
+    /// [`StartTls::is_binary`].
+    pub status: Status,
+    /// The number of bytes received after the STARTTLS response, see
+    /// [`Upgrade::remaining`].
+    pub trailing_bytes: usize,
+    /// The capabilities advertised by the server, see
+    /// [`StartTls::capabilities`].
+    pub capabilities: Vec<String>,
+}
+
+/// The parsed status of a response line.
+///
+/// See [`StartTls::status`] and [`CompletionReport::status`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Status {
+    /// A numeric reply code, like `220` for SMTP or `670` for IRC.
+    Code(u16),
+    /// A status keyword, like `OK` for IMAP, `+OK` for POP3 or
+    /// `proceed` for XMPP.
+    Keyword(String),
+    /// No status, like for binary protocols.
+    #[default]
+    None,
+}
+
+impl Status {
+    /// Parses the given status token, as returned by
+    /// [`StartTls::status`].
+    pub fn parse(token: &[u8]) -> Self {
+        if token.is_empty() {
+            return Self::None;
+        }
+
+        let token = String::from_utf8_lossy(token);
+
+        match token.parse() {
+            Ok(code) if token.bytes().all(|b| b.is_ascii_digit()) => Self::Code(code),
+            _ => Self::Keyword(token.into_owned()),
+        }
+    }
+}
+
+/// The I/O statistics of an [`Upgrade`] flow.
+///
+/// See [`Upgrade::stats`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// The number of reads processed by the coroutine.
+    pub reads: usize,
+    /// The number of writes processed by the coroutine, partial ones
+    /// included.
+    pub writes: usize,
+}
+
+#[cfg(feature = "io-stream")]
+/// The I/O the [`Upgrade`] coroutine is waiting for.
+#[derive(Clone, Debug)]
+enum Pending {
+    /// No I/O is needed, the flow just needs to be advanced.
+    Advance,
+    /// Bytes need to be read before advancing the flow.
+    Read,
+    /// The given command needs to be written before advancing the
+    /// flow.
+    Write(Write),
+}
+
+#[cfg(feature = "io-stream")]
+/// The generic STARTTLS coroutine that upgrades a plain (TCP) stream
+/// to a secure one.
+///
+/// Each protocol module exposes its own `UpgradeTls` alias of this
+/// coroutine, like `imap::UpgradeTls`.
+#[derive(Clone, Debug)]
+pub struct Upgrade<P> {
+    flow: Flow<P>,
+    read: Read,
+    read_capacity: usize,
+    pending: Pending,
+    stats: Stats,
+}
+
+#[cfg(feature = "io-stream")]
+impl<P: StartTls + Default> Upgrade<P> {
+    /// Creates a new STARTTLS coroutine with sane defaults.
+    pub fn new() -> Self {
+        Self::from_protocol(P::default())
+    }
+
+    /// Creates a new STARTTLS coroutine for a stream that has already
+    /// been used, like one established through a SOCKS or HTTP
+    /// proxy.
+    ///
+    /// The coroutine does not discard the greeting and starts with an
+    /// empty buffer, whatever the defaults of [`Upgrade::new`] are:
+    /// the greeting may have been consumed by the proxy negotiation.
+    /// If the greeting, or part of it, has been read before, it can
+    /// be handed back via [`Upgrade::with_buffer`] together with
+    /// [`Upgrade::with_discard_greeting`].
+    pub fn for_connected() -> Self {
+        let mut upgrade = Self::new();
+        upgrade.discard_greeting(false);
+        upgrade.set_buffer(Vec::new());
+        upgrade
+    }
+
+    /// Creates a new STARTTLS coroutine starting at the given step,
+    /// with its buffer seeded with the given bytes.
+    ///
+    /// This is a low-level alternative to chaining
+    /// [`Upgrade::with_starting_state`] and [`Upgrade::with_buffer`],
+    /// for coroutines embedded in a larger state machine. The bytes
+    /// need to match the starting step, since they are processed as
+    /// if they were just read from the stream:
+    ///
+    /// - [`StartingState::DiscardGreeting`]: the bytes are the
+    ///   beginning of the greeting, or the whole greeting optionally
+    ///   followed by the beginning of the next response.
+    /// - [`StartingState::WriteCommand`]: the greeting has been fully
+    ///   consumed, and no command has been written yet. The bytes are
+    ///   usually empty, since the server is not supposed to send
+    ///   anything before the first command.
+    /// - [`StartingState::DiscardResponse`]: the STARTTLS command has
+    ///   already been written, and the bytes are the beginning of its
+    ///   response. No prelude command can be queued or required by
+    ///   the protocol, and protocols tracking commands (like the IMAP
+    ///   tag) need to match the written command.
+    pub fn from_parts(state: StartingState, buffer: Vec<u8>) -> Self {
+        Self::new().with_starting_state(state).with_buffer(buffer)
+    }
+}
+
+#[cfg(feature = "io-stream")]
+impl<P: StartTls> Upgrade<P> {
+    /// Creates a new STARTTLS coroutine for the given protocol.
+    pub fn from_protocol(protocol: P) -> Self {
+        Self {
+            flow: Flow::from_protocol(protocol),
+            read: Read::default(),
+            read_capacity: Read::DEFAULT_CAPACITY,
+            pending: Pending::Advance,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Returns a reference to the protocol.
+    pub fn protocol(&self) -> &P {
+        &self.flow.protocol
+    }
+
+    /// Returns a mutable reference to the protocol.
+    pub fn protocol_mut(&mut self) -> &mut P {
+        &mut self.flow.protocol
+    }
+
+    /// Tells the coroutine how to handle the greeting.
+    ///
+    /// When enabled, the coroutine reads and discards the greeting
+    /// from the plain stream before writing the STARTTLS command.
+    /// Disabled by default, which is useful when the greeting has
+    /// already been read before: in this case, the coroutine will
+    /// directly write the STARTTLS command.
+    ///
+    /// It also needs to be disabled for servers sending no greeting
+    /// at all, and waiting for the STARTTLS command right away (like
+    /// some test servers): otherwise the coroutine waits for a
+    /// greeting that never comes. Since the server sends nothing, the
+    /// coroutine cannot tell such servers apart from slow ones, and
+    /// only a read timeout of the transport can interrupt the wait.
+    ///
+    /// See also [`Upgrade::with_discard_greeting`] for the builder
+    /// alternative.
+    pub fn discard_greeting(&mut self, discard: bool) {
+        self.flow.discard_greeting(discard);
+    }
+
+    /// Builder alternative to [`Upgrade::discard_greeting`].
+    pub fn with_discard_greeting(mut self, discard: bool) -> Self {
+        self.discard_greeting(discard);
+        self
+    }
+
+    /// Tells the coroutine to read the greeting and to keep it for
+    /// [`Upgrade::greeting`].
+    ///
+    /// The greeting read by the coroutine is never thrown away: it is
+    /// always kept for [`Upgrade::greeting`], whether it was
+    /// captured or discarded. This is then an alias of
+    /// [`Upgrade::discard_greeting`], for clients that need the
+    /// greeting to identify the server while still letting the
+    /// coroutine drive the whole flow.
+    ///
+    /// See also [`Upgrade::with_capture_greeting`] for the builder
+    /// alternative.
+    pub fn capture_greeting(&mut self, capture: bool) {
+        self.discard_greeting(capture);
+    }
+
+    /// Builder alternative to [`Upgrade::capture_greeting`].
+    pub fn with_capture_greeting(mut self, capture: bool) -> Self {
+        self.capture_greeting(capture);
+        self
+    }
+
+    /// Overrides the STARTTLS command.
+    ///
+    /// The given bytes are written as is, in place of the command
+    /// built by the protocol. This may be useful for proxy-specific
+    /// verbs or servers expecting a slightly different command. Note
+    /// that the response is still parsed by the protocol, which may
+    /// need to be configured accordingly (like the IMAP response
+    /// marker). The command needs to fit in a single line: line
+    /// terminators are only allowed at its end, otherwise the
+    /// coroutine fails with [`UpgradeTlsError::InvalidConfig`] before
+    /// writing anything.
+    ///
+    /// This allows servers only accepting a lowercase `starttls` to
+    /// be supported. The reverse is not needed: response statuses
+    /// like `OK` are always matched case-insensitively.
+    ///
+    /// See also [`Upgrade::with_command`] for the builder
+    /// alternative.
+    pub fn set_command(&mut self, command: impl Into<Vec<u8>>) {
+        self.flow.set_command(command);
+    }
+
+    /// Builder alternative to [`Upgrade::set_command`].
+    pub fn with_command(mut self, command: impl Into<Vec<u8>>) -> Self {
+        self.set_command(command);
+        self
+    }
+
+    /// Queues commands to write before the STARTTLS one.
+    ///
+    /// Commands are written one by one, and the response of each
+    /// command is consumed before writing the next one. This may be
+    /// useful to send setup commands, like the SMTP `EHLO`, without
+    /// running an extra coroutine. The coroutine fails as soon as one
+    /// of these commands is rejected. Commands need to be terminated
+    /// the way the protocol expects (usually with `\r\n`), and like
+    /// [`Upgrade::set_command`] they need to fit in a single line.
+    ///
+    /// See also [`Upgrade::with_prelude`] for the builder
+    /// alternative.
+    pub fn set_prelude(&mut self, commands: Vec<Vec<u8>>) {
+        self.flow.set_prelude(commands);
+    }
+
+    /// Builder alternative to [`Upgrade::set_prelude`].
+    pub fn with_prelude(mut self, commands: Vec<Vec<u8>>) -> Self {
+        self.set_prelude(commands);
+        self
+    }
+
+    /// Tells the coroutine to skip the STARTTLS command.
+    ///
+    /// When enabled, the coroutine directly reads the STARTTLS
+    /// response, without discarding the greeting or writing any
+    /// command. This may be useful when the STARTTLS command has
+    /// already been sent by another code path. Since no command is
+    /// built, the protocol needs to be configured accordingly (like
+    /// the IMAP tag to look for).
+    ///
+    /// See also [`Upgrade::with_skip_command`] for the builder
+    /// alternative.
+    pub fn set_skip_command(&mut self, skip: bool) {
+        self.flow.set_skip_command(skip);
+    }
+
+    /// Builder alternative to [`Upgrade::set_skip_command`].
+    pub fn with_skip_command(mut self, skip: bool) -> Self {
+        self.set_skip_command(skip);
+        self
+    }
+
+    /// Sets the step the flow starts with.
+    ///
+    /// This is an alternative to [`Upgrade::discard_greeting`] and
+    /// [`Upgrade::set_skip_command`], which configures both at once.
+    ///
+    /// See also [`Upgrade::with_starting_state`] for the builder
+    /// alternative.
+    pub fn set_starting_state(&mut self, state: StartingState) {
+        self.flow.set_starting_state(state);
+    }
+
+    /// Builder alternative to [`Upgrade::set_starting_state`].
+    pub fn with_starting_state(mut self, state: StartingState) -> Self {
+        self.set_starting_state(state);
+        self
+    }
+
+    /// Returns the step the flow starts with.
+    ///
+    /// Fails with [`UpgradeTlsError::InvalidConfig`] if the
+    /// configuration is not consistent, like a skipped STARTTLS
+    /// command combined with prelude commands. The coroutine performs
+    /// the same check when it starts.
+    pub fn starting_state(&self) -> Result<StartingState, UpgradeTlsError> {
+        self.flow.starting_state()
+    }
+
+    /// Seeds the coroutine with bytes already read from the plain
+    /// stream.
+    ///
+    /// This setter may be useful if some bytes, like a partial
+    /// greeting, have been read before the coroutine was built: when
+    /// discarding the greeting, the coroutine first looks for it in
+    /// these bytes, and only reads from the stream if the greeting is
+    /// incomplete.
+    ///
+    /// Bytes remaining after a step are always carried over to the
+    /// next one, so a greeting and a response received within the
+    /// same read are both processed.
+    ///
+    /// See also [`Upgrade::with_buffer`] for the builder alternative.
+    pub fn set_buffer(&mut self, bytes: Vec<u8>) {
+        self.flow.set_buffer(bytes);
+    }
+
+    /// Builder alternative to [`Upgrade::set_buffer`].
+    pub fn with_buffer(mut self, bytes: Vec<u8>) -> Self {
+        self.set_buffer(bytes);
+        self
+    }
+
+    /// Appends bytes read from the plain stream to the buffer.
+    ///
+    /// Unlike [`Upgrade::set_buffer`], this function can be called at
+    /// any time during the flow. This may be useful when the plain
+    /// stream is wrapped in a buffered reader: bytes pulled in the
+    /// reader buffer are handed to the coroutine, so that they are
+    /// processed like any other read bytes, and so that the strict
+    /// mode accounts for them.
+    pub fn extend_buffer(&mut self, bytes: &[u8]) {
+        self.flow.bytes.extend_from_slice(bytes);
+    }
+
+    /// Sets the capacity of the buffer used to read from the plain
+    /// stream.
+    ///
+    /// Defaults to [`Read::DEFAULT_CAPACITY`]. A larger buffer allows
+    /// large greetings or responses (like long multiline SMTP
+    /// banners) to be received in fewer reads, at the cost of a
+    /// bigger allocation held during the whole flow.
+    ///
+    /// See also [`Upgrade::with_read_capacity`] for the builder
+    /// alternative.
+    pub fn set_read_capacity(&mut self, capacity: usize) {
+        self.read = Read::new(capacity);
+        self.read_capacity = capacity;
+    }
+
+    /// Builder alternative to [`Upgrade::set_read_capacity`].
+    pub fn with_read_capacity(mut self, capacity: usize) -> Self {
+        self.set_read_capacity(capacity);
+        self
+    }
+
+    /// Sets the maximum number of consecutive reads that can be
+    /// performed without completing the current step.
+    ///
+    /// By default, the coroutine reads until the expected greeting or
+    /// response is received, which never happens if the server hangs
+    /// or trickles bytes forever. Once the limit is reached, the
+    /// coroutine fails with [`UpgradeTlsError::Timeout`]. This keeps
+    /// the coroutine runtime-agnostic, as no clock is involved.
+    ///
+    /// See also [`Upgrade::with_max_reads`] for the builder
+    /// alternative.
+    pub fn set_max_reads(&mut self, max: usize) {
+        self.flow.set_max_reads(max);
+    }
+
+    /// Builder alternative to [`Upgrade::set_max_reads`].
+    pub fn with_max_reads(mut self, max: usize) -> Self {
+        self.set_max_reads(max);
+        self
+    }
+
+    /// Sets the maximum number of bytes that can be buffered without
+    /// completing the current step.
+    ///
+    /// A malicious server could otherwise stream bytes forever
+    /// without ever terminating its greeting or response, exhausting
+    /// memory. Once the limit is reached, the coroutine fails with
+    /// [`UpgradeTlsError::LineTooLong`]. Defaults to
+    /// [`DEFAULT_MAX_BUFFER`].
+    ///
+    /// See also [`Upgrade::with_max_buffer`] for the builder
+    /// alternative.
+    pub fn set_max_buffer(&mut self, max: usize) {
+        self.flow.set_max_buffer(max);
+    }
+
+    /// Builder alternative to [`Upgrade::set_max_buffer`].
+    pub fn with_max_buffer(mut self, max: usize) -> Self {
+        self.set_max_buffer(max);
+        self
+    }
+
+    /// Sets the observer notified of every state transition.
+    ///
+    /// The observer is called at the same points the coroutine logs
+    /// its progress, which may be useful to feed metrics (like how
+    /// often STARTTLS is rejected) without scraping logs. The
+    /// observer is shared between clones of the coroutine.
+    ///
+    /// See also [`Upgrade::with_observer`] for the builder
+    /// alternative.
+    #[cfg(feature = "std")]
+    pub fn set_observer(&mut self, f: impl FnMut(Event) + Send + 'static) {
+        self.flow.set_observer(f);
+    }
+
+    /// Builder alternative to [`Upgrade::set_observer`].
+    #[cfg(feature = "std")]
+    pub fn with_observer(mut self, f: impl FnMut(Event) + Send + 'static) -> Self {
+        self.set_observer(f);
+        self
+    }
+
+    /// Returns the [`futures::Stream`] of the events emitted by the
+    /// coroutine.
+    ///
+    /// This is an async alternative to [`Upgrade::set_observer`],
+    /// which allows UIs to display the progress of the upgrade while
+    /// it runs. It replaces the observer set before, if any. The
+    /// stream ends once the coroutine and its clones are dropped, so
+    /// the coroutine is usually moved into the future running it:
+    ///
+    /// ```rust,ignore
+    /// let events = starttls.events();
+    /// let upgrade = async move { starttls.run_futures(&mut tcp).await };
+    /// let (result, ()) = futures::join!(upgrade, events.for_each(display));
+    /// ```
+    #[cfg(feature = "futures")]
+    pub fn events(&mut self) -> impl futures::Stream<Item = Event> + Send + Unpin {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+
+        self.set_observer(move |event| {
+            // the stream may have been dropped before the coroutine,
+            // in which case events are not needed anymore
+            let _ = tx.unbounded_send(event);
+        });
+
+        rx
+    }
+
+    /// Tells the coroutine to record the raw transcript of the bytes
+    /// exchanged with the server.
+    ///
+    /// Disabled by default, since it keeps a copy of every byte. When
+    /// enabled, the transcript is available via
+    /// [`Upgrade::transcript`], which may be useful to debug interop
+    /// issues with a specific server.
+    ///
+    /// See also [`Upgrade::with_transcript`] for the builder
+    /// alternative.
+    pub fn set_transcript(&mut self, enabled: bool) {
+        self.flow.set_transcript(enabled);
+    }
+
+    /// Builder alternative to [`Upgrade::set_transcript`].
+    pub fn with_transcript(mut self, enabled: bool) -> Self {
+        self.set_transcript(enabled);
+        self
+    }
+
+    /// Returns the raw transcript of the bytes exchanged with the
+    /// server.
+    ///
+    /// Every line is prefixed by its direction: `C: ` for bytes
+    /// written by the client, `S: ` for bytes read from the server.
+    /// The transcript is empty unless [`Upgrade::set_transcript`] is
+    /// enabled.
+    pub fn transcript(&self) -> &[u8] {
+        self.flow.transcript()
+    }
+
+    /// Tells the coroutine how to handle bytes received after the
+    /// STARTTLS response.
+    ///
+    /// By default, such bytes are kept aside and exposed via
+    /// [`Upgrade::remaining`]. In strict mode, the coroutine fails
+    /// with [`UpgradeTlsError::TrailingBytes`] instead, since they
+    /// may have been injected by an attacker before the TLS
+    /// handshake.
+    ///
+    /// See also [`Upgrade::with_strict_buffer`] for the builder
+    /// alternative.
+    pub fn set_strict_buffer(&mut self, strict: bool) {
+        self.flow.set_strict_buffer(strict);
+    }
+
+    /// Builder alternative to [`Upgrade::set_strict_buffer`].
+    pub fn with_strict_buffer(mut self, strict: bool) -> Self {
+        self.set_strict_buffer(strict);
+        self
+    }
+
+    /// Sets the handler called with the bytes received after the
+    /// STARTTLS response.
+    ///
+    /// The handler is called exactly once, when the STARTTLS response
+    /// has been received, with the bytes that follow it (possibly
+    /// none). Unlike the strict buffer mode, it allows trailing bytes
+    /// to be audited, like logging injection attempts, without
+    /// failing the flow. The handler is shared between clones of the
+    /// coroutine.
+    ///
+    /// See also [`Upgrade::with_trailing_handler`] for the builder
+    /// alternative.
+    #[cfg(feature = "std")]
+    pub fn set_trailing_handler(&mut self, f: impl FnMut(&[u8]) + Send + 'static) {
+        self.flow.set_trailing_handler(f);
+    }
+
+    /// Builder alternative to [`Upgrade::set_trailing_handler`].
+    #[cfg(feature = "std")]
+    pub fn with_trailing_handler(mut self, f: impl FnMut(&[u8]) + Send + 'static) -> Self {
+        self.set_trailing_handler(f);
+        self
+    }
+
+    /// Sets the token telling that the server accepted the STARTTLS
+    /// command.
+    ///
+    /// By default, each protocol knows its own success status, like
+    /// `OK` for IMAP or `220` for SMTP. When set, the status token of
+    /// the response line (see [`StartTls::status`]) is compared to
+    /// the given one instead, case-insensitively. This helps with
+    /// servers answering with a non-standard status. It only applies
+    /// to the STARTTLS response of text protocols, and is ignored by
+    /// binary ones (see [`StartTls::is_binary`]). Other errors like
+    /// [`UpgradeTlsError::ServerClosing`] are still reported as is.
+    ///
+    /// See also [`Upgrade::with_success_token`] for the builder
+    /// alternative.
+    pub fn set_success_token(&mut self, token: impl Into<String>) {
+        self.flow.set_success_token(token);
+    }
+
+    /// Builder alternative to [`Upgrade::set_success_token`].
+    pub fn with_success_token(mut self, token: impl Into<String>) -> Self {
+        self.set_success_token(token);
+        self
+    }
+
+    /// Sets the number of times the STARTTLS command is written again
+    /// after a transient rejection.
+    ///
+    /// Some servers reject the command under load, like IMAP servers
+    /// answering `NGC6543 NO [UNAVAILABLE] try again`. The command is
+    /// then written again, up to the given number of times, before
+    /// failing with the last rejection. Rejections are transient when
+    /// they match the predicate set via
+    /// [`Upgrade::set_retry_predicate`], which defaults to
+    /// [`StartTls::is_transient`]: rejections holding the
+    /// `[UNAVAILABLE]` response code or a `4xx` status, or the
+    /// `[IN-USE]` and `[SYS/TEMP]` codes of POP3. IMAP commands are
+    /// written again with a fresh tag, see [`StartTls::retry`].
+    /// Defaults to 0, which disables retries.
+    ///
+    /// See also [`Upgrade::with_retries`] for the builder
+    /// alternative.
+    pub fn set_retries(&mut self, retries: usize) {
+        self.flow.set_retries(retries);
+    }
+
+    /// Builder alternative to [`Upgrade::set_retries`].
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.set_retries(retries);
+        self
+    }
+
+    /// Sets the predicate telling if a rejection of the STARTTLS
+    /// command is transient, overriding the default one.
+    ///
+    /// The predicate receives the rejection line, lossy-decoded from
+    /// UTF-8. It is only called when retries are enabled via
+    /// [`Upgrade::set_retries`].
+    ///
+    /// See also [`Upgrade::with_retry_predicate`] for the builder
+    /// alternative.
+    pub fn set_retry_predicate(&mut self, f: impl Fn(&str) -> bool + Send + Sync + 'static) {
+        self.flow.set_retry_predicate(f);
+    }
+
+    /// Builder alternative to [`Upgrade::set_retry_predicate`].
+    pub fn with_retry_predicate(
+        mut self,
+        f: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.set_retry_predicate(f);
+        self
+    }
+
+    /// Sets the predicate telling if the greeting is complete,
+    /// overriding the one of the protocol.
+    ///
+    /// The predicate receives all the bytes received so far, and
+    /// returns `None` as long as more bytes are needed, otherwise the
+    /// index past which the greeting ends. This allows servers with
+    /// non-standard banners, like a banner followed by a blank line,
+    /// to be supported. Protocols collecting data from the greeting,
+    /// like IMAP capabilities, do not see it anymore. An index of 0
+    /// or past the received bytes makes the coroutine fail with
+    /// [`UpgradeTlsError::InvalidConfig`].
+    ///
+    /// See also [`Upgrade::with_greeting_complete`] for the builder
+    /// alternative.
+    pub fn set_greeting_complete(
+        &mut self,
+        f: impl Fn(&[u8]) -> Option<usize> + Send + Sync + 'static,
+    ) {
+        self.flow.set_greeting_complete(f);
+    }
+
+    /// Builder alternative to [`Upgrade::set_greeting_complete`].
+    pub fn with_greeting_complete(
+        mut self,
+        f: impl Fn(&[u8]) -> Option<usize> + Send + Sync + 'static,
+    ) -> Self {
+        self.set_greeting_complete(f);
+        self
+    }
+
+    /// Tells the coroutine to reject non-ASCII greetings and
+    /// responses.
+    ///
+    /// Text protocols like IMAP or SMTP only exchange ASCII before
+    /// the TLS handshake, while non-ASCII bytes are lossy-decoded by
+    /// default. In strict mode, the coroutine fails with
+    /// [`UpgradeTlsError::NonAscii`] instead, which lets
+    /// security-conscious users bail on mangled banners, like the
+    /// ones injected by some proxies. This mode should not be enabled
+    /// for binary protocols like LDAP, MySQL or PostgreSQL.
+    ///
+    /// See also [`Upgrade::with_strict_ascii`] for the builder
+    /// alternative.
+    pub fn set_strict_ascii(&mut self, strict: bool) {
+        self.flow.set_strict_ascii(strict);
+    }
+
+    /// Builder alternative to [`Upgrade::set_strict_ascii`].
+    pub fn with_strict_ascii(mut self, strict: bool) -> Self {
+        self.set_strict_ascii(strict);
+        self
+    }
+
+    /// Tells the coroutine to drain complete lines from the buffer
+    /// before reading more bytes.
+    ///
+    /// By default, the buffer grows until the current step completes,
+    /// which can be large for verbose multiline banners. When
+    /// enabled, complete lines that did not complete the step are
+    /// discarded as soon as more bytes are needed, so the buffer only
+    /// holds the last incomplete line. Protocols tell which bytes can
+    /// be drained (see [`StartTls::drain_offset`]): draining is
+    /// supported by IMAP, SMTP, LMTP and ManageSieve, and is a no-op
+    /// for other protocols, whose completion checks need the whole
+    /// response (like FTP multiline replies or POP3 capabilities).
+    /// Note that [`Upgrade::greeting`] then only contains the last
+    /// lines of the greeting.
+    ///
+    /// See also [`Upgrade::with_drain_lines`] for the builder
+    /// alternative.
+    pub fn set_drain_lines(&mut self, drain: bool) {
+        self.flow.set_drain_lines(drain);
+    }
+
+    /// Builder alternative to [`Upgrade::set_drain_lines`].
+    pub fn with_drain_lines(mut self, drain: bool) -> Self {
+        self.set_drain_lines(drain);
+        self
+    }
+
+    /// Tells the coroutine to skip its logs.
+    ///
+    /// Logs contain the greeting and the responses received from the
+    /// server, which may be sensitive for some connections. When
+    /// enabled, these logs are skipped for this coroutine only, which
+    /// is finer-grained than a global log filter.
+    ///
+    /// See also [`Upgrade::with_quiet`] for the builder alternative.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.flow.set_quiet(quiet);
+    }
+
+    /// Builder alternative to [`Upgrade::set_quiet`].
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.set_quiet(quiet);
+        self
+    }
+
+    /// Returns the greeting received from the server.
+    ///
+    /// The greeting is lossy-decoded from UTF-8. It is only available
+    /// when [`Upgrade::discard_greeting`] (or its
+    /// [`Upgrade::capture_greeting`] alias) is enabled, once the
+    /// coroutine consumed it. This may be useful to identify the
+    /// server without sending extra commands.
+    pub fn greeting(&self) -> Option<&str> {
+        self.flow.greeting.as_deref()
+    }
+
+    /// Tells if the server requires the stream to be upgraded.
+    ///
+    /// This is based on what the server sent during the flow, like
+    /// the IMAP `[PRIVACYREQUIRED]` response code or the SMTP `530
+    /// 5.7.0 Must issue a STARTTLS command first` reply, and may help
+    /// account setup wizards to tell users that the server requires
+    /// encryption. Returns `None` when the server did not tell
+    /// anything, see [`StartTls::tls_required`].
+    pub fn tls_required(&self) -> Option<bool> {
+        self.flow.protocol.tls_required()
+    }
+
+    /// Returns the STARTTLS response received from the server.
+    ///
+    /// The response is the line completing the STARTTLS response
+    /// (like `NGC6543 OK Begin TLS negotiation now` for IMAP),
+    /// lossy-decoded from UTF-8 and without its line ending. It is
+    /// available once the coroutine consumed it, whether the server
+    /// accepted the command or not.
+    pub fn response(&self) -> Option<&str> {
+        self.flow.response.as_deref()
+    }
+
+    /// Returns the bytes received after the STARTTLS response.
+    ///
+    /// Bytes received after the response are kept aside rather than
+    /// being discarded. Since they arrived before the TLS handshake,
+    /// they should be considered as untrusted plaintext. This is only
+    /// meaningful once the coroutine completed.
+    ///
+    /// See also [`Upgrade::into_remaining`] for the owned
+    /// alternative.
+    pub fn remaining(&self) -> &[u8] {
+        &self.flow.bytes
+    }
+
+    /// Owned alternative to [`Upgrade::remaining`].
+    pub fn into_remaining(self) -> Vec<u8> {
+        self.flow.bytes
+    }
+
+    /// Returns the I/O statistics of the coroutine.
+    ///
+    /// Statistics tell how many reads and writes the coroutine had to
+    /// process so far, which helps spotting servers that trickle data
+    /// and tuning [`Upgrade::set_read_capacity`].
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Returns the exact STARTTLS command bytes the coroutine is going
+    /// to write, tag and line terminator included.
+    ///
+    /// This allows the wire format to be audited without running the
+    /// coroutine, like checking that no unexpected line got injected
+    /// via the tag. Prelude commands are not included. Note that when
+    /// a tag generator is set, the tag is regenerated once the flow
+    /// starts.
+    pub fn command_bytes(&self) -> &[u8] {
+        self.flow.command()
+    }
+
+    /// Turns the coroutine into its sans-io flow.
+    ///
+    /// The flow keeps the configuration of the coroutine, and may be
+    /// driven by feeding bytes from any transport instead of relying
+    /// on [`io_stream`]. Note that the read capacity is not relevant
+    /// to the flow, since the transport owns its buffers.
+    pub fn into_flow(self) -> Flow<P> {
+        self.flow
+    }
+
+    /// Checks that no plaintext byte is left, neither in the
+    /// coroutine buffer nor in the given external buffer.
+    ///
+    /// This should be called once the coroutine completed, with the
+    /// content of any buffered reader wrapping the plain stream:
+    /// bytes buffered there arrived before the TLS handshake, and
+    /// would otherwise escape the strict mode. Fails with
+    /// [`UpgradeTlsError::TrailingBytes`] if any byte is left.
+    pub fn check_remaining(&self, external: &[u8]) -> Result<(), UpgradeTlsError> {
+        if self.flow.bytes.is_empty() && external.is_empty() {
+            return Ok(());
+        }
+
+        let mut bytes = self.flow.bytes.clone();
+        bytes.extend_from_slice(external);
+        Err(UpgradeTlsError::TrailingBytes(bytes))
+    }
+
+    /// Resets the coroutine, so that it can be resumed again from
+    /// the beginning.
+    ///
+    /// The configuration is kept (like the tag, the greeting
+    /// discarding, the buffer limits or the observer), while the
+    /// buffer, the greeting, the transcript, the statistics and any
+    /// state collected during the previous flow are cleared. The
+    /// coroutine may be reset at any point, even while waiting for
+    /// I/O. This allows a configured coroutine to be used as a
+    /// prototype, for example by cloning it for every new connection
+    /// of a pool, or to be recycled across reconnections.
+    pub fn reset(&mut self) {
+        self.flow.reset();
+        // the read buffer may have been handed over to a pending I/O
+        // request, which will never be answered
+        self.read = Read::new(self.read_capacity);
+        self.pending = Pending::Advance;
+        self.stats = Stats::default();
+    }
+
+    /// Makes the coroutine progress.
+    ///
+    /// The coroutine returns `Err(io)` as long as it needs I/O to be
+    /// processed. Once the STARTTLS response has been received, it
+    /// returns `Ok(Ok(()))` if the server accepted the command,
+    /// otherwise `Ok(Err(err))`.
+    pub fn resume(&mut self, mut io: Option<Io>) -> Result<Result<(), UpgradeTlsError>, Io> {
+        loop {
+            let step = match &mut self.pending {
+                Pending::Advance => self.flow.advance(&[]),
+                Pending::Read => {
+                    let mut output = self.read.resume(io.take())?;
+                    self.stats.reads += 1;
+                    let step = self
+                        .flow
+                        .advance_buffer(&mut output.buffer, output.bytes_count);
+                    // the buffer may have been swapped with the flow
+                    // one, so it is resized back to the read capacity
+                    output.buffer.resize(self.read_capacity, 0);
+                    self.read.replace(output.buffer);
+                    step
+                }
+                Pending::Write(write) => {
+                    let flushed = flush(write, io.take())?;
+                    self.stats.writes += 1;
+
+                    match flushed {
+                        Ok(true) => self.flow.advance(&[]),
+                        Ok(false) => continue,
+                        Err(err) => Step::Done(Err(err)),
+                    }
+                }
+            };
+
+            match step {
+                Step::NeedMore => self.pending = Pending::Read,
+                Step::Write(command) => self.pending = Pending::Write(Write::new(command)),
+                Step::Done(result) => {
+                    self.pending = Pending::Advance;
+                    break Ok(result);
+                }
+            }
+        }
+    }
+
+    /// Makes the coroutine progress, like [`Upgrade::resume`].
+    ///
+    /// Once the server accepted the STARTTLS command, the coroutine
+    /// returns `Ok(Ok(progress))` instead, which tells what happened
+    /// during the flow.
+    pub fn resume_progress(
+        &mut self,
+        io: Option<Io>,
+    ) -> Result<Result<Progress, UpgradeTlsError>, Io> {
+        match self.resume(io)? {
+            Ok(()) => Ok(Ok(self.flow.progress.clone())),
+            Err(err) => Ok(Err(err)),
+        }
+    }
+
+    /// Makes the coroutine progress, like [`Upgrade::resume`].
+    ///
+    /// Once the server accepted the STARTTLS command, the coroutine
+    /// returns `Ok(Ok(report))` instead, which gathers what has been
+    /// received from the server during the flow.
+    pub fn resume_report(
+        &mut self,
+        io: Option<Io>,
+    ) -> Result<Result<CompletionReport, UpgradeTlsError>, Io> {
+        let Progress { response, .. } = match self.resume_progress(io)? {
+            Ok(progress) => progress,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        let status = match self.flow.protocol.is_binary() {
+            true => Status::None,
+            false => Status::parse(self.flow.protocol.status(response.as_bytes())),
+        };
+
+        Ok(Ok(CompletionReport {
+            greeting: self.flow.greeting.clone(),
+            response,
+            status,
+            trailing_bytes: self.flow.bytes.len(),
+            capabilities: self.flow.protocol.capabilities().to_vec(),
+        }))
+    }
+
+    /// Runs the coroutine to completion against the given blocking
+    /// stream, using the standard runtime of [`io_stream`].
+    ///
+    /// A rejected STARTTLS command is reported as an I/O error.
+    #[cfg(feature = "std")]
+    pub fn run_blocking(
+        &mut self,
+        mut stream: impl std::io::Read + std::io::Write,
+    ) -> std::io::Result<()> {
+        use io_stream::runtimes::std::handle;
+
+        let mut input = None;
+
+        let result = loop {
+            match self.resume(input) {
+                Ok(result) => break result,
+                Err(io) => input = Some(handle(&mut stream, io)?),
+            }
+        };
+
+        Ok(result?)
+    }
+
+    /// Returns an iterator running the coroutine against the given
+    /// blocking stream, using the standard runtime of [`io_stream`].
+    ///
+    /// This is a step-by-step alternative to
+    /// [`Upgrade::run_blocking`]: the iterator yields one item per
+    /// processed I/O request, and ends once the coroutine completed.
+    /// A rejected STARTTLS command is yielded as a final I/O error,
+    /// so the whole flow can be collected into an
+    /// `std::io::Result`.
+    #[cfg(feature = "std")]
+    pub fn blocking_steps<S>(&mut self, stream: S) -> BlockingSteps<'_, P, S>
+    where
+        S: std::io::Read + std::io::Write,
+    {
+        BlockingSteps {
+            upgrade: self,
+            stream,
+            input: None,
+            done: false,
+        }
+    }
+
+    /// Runs the coroutine to completion against the given blocking
+    /// stream, then performs the TLS handshake using [`rustls`].
+    ///
+    /// Returns the secure stream, ready to be used.
+    #[cfg(feature = "rustls")]
+    pub fn run_rustls<S: std::io::Read + std::io::Write>(
+        &mut self,
+        mut stream: S,
+        server_name: rustls::pki_types::ServerName<'static>,
+        config: std::sync::Arc<rustls::ClientConfig>,
+    ) -> std::io::Result<rustls::StreamOwned<rustls::ClientConnection, S>> {
+        self.run_blocking(&mut stream)?;
+
+        let mut conn =
+            rustls::ClientConnection::new(config, server_name).map_err(std::io::Error::other)?;
+
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)?;
+        }
+
+        Ok(rustls::StreamOwned::new(conn, stream))
+    }
+
+    /// Runs the coroutine to completion against the given blocking
+    /// stream, then performs the TLS handshake using [`openssl`].
+    ///
+    /// Returns the secure stream, ready to be used.
+    #[cfg(feature = "openssl")]
+    pub fn run_openssl<S: std::io::Read + std::io::Write>(
+        &mut self,
+        mut stream: S,
+        domain: &str,
+        connector: &openssl::ssl::SslConnector,
+    ) -> std::io::Result<openssl::ssl::SslStream<S>> {
+        use openssl::ssl::HandshakeError;
+
+        self.run_blocking(&mut stream)?;
+
+        match connector.connect(domain, stream) {
+            Ok(stream) => Ok(stream),
+            Err(HandshakeError::SetupFailure(err)) => Err(std::io::Error::other(err)),
+            Err(HandshakeError::Failure(stream)) => Err(std::io::Error::other(stream.into_error())),
+            // blocking streams are never interrupted mid-handshake,
+            // unless they have been configured as non-blocking
+            Err(HandshakeError::WouldBlock(stream)) => Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                stream.into_error(),
+            )),
+        }
+    }
+
+    /// Runs the coroutine to completion against the given async
+    /// stream, using the tokio runtime of [`io_stream`].
+    ///
+    /// A rejected STARTTLS command is reported as an I/O error.
+    #[cfg(feature = "tokio")]
+    pub async fn run_tokio(
+        &mut self,
+        mut stream: impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    ) -> std::io::Result<()> {
+        use io_stream::runtimes::tokio::handle;
+
+        let mut input = None;
+
+        let result = loop {
+            match self.resume(input) {
+                Ok(result) => break result,
+                Err(io) => input = Some(handle(&mut stream, io).await?),
+            }
+        };
+
+        Ok(result?)
+    }
+
+    /// Runs the coroutine to completion like [`Upgrade::run_tokio`],
+    /// within the given duration.
+    ///
+    /// If the STARTTLS phase does not complete in time, a
+    /// [`std::io::ErrorKind::TimedOut`] error is returned. This is
+    /// the async counterpart of [`Upgrade::set_max_reads`], bounding
+    /// the time spent rather than the number of reads.
+    #[cfg(feature = "tokio")]
+    pub async fn run_tokio_timeout(
+        &mut self,
+        stream: impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+        duration: core::time::Duration,
+    ) -> std::io::Result<()> {
+        match tokio::time::timeout(duration, self.run_tokio(stream)).await {
+            Ok(result) => result,
+            Err(_) => {
+                let err = "STARTTLS upgrade timed out";
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, err))
+            }
+        }
+    }
+
+    /// Runs the coroutine to completion against the given async
+    /// stream implementing the [`futures`] I/O traits, like the ones
+    /// of smol or async-std.
+    ///
+    /// Since [`io_stream`] does not provide any runtime for these
+    /// traits, I/O requests are processed by a minimal glue reading
+    /// into and writing from the buffers they carry.
+    ///
+    /// A rejected STARTTLS command is reported as an I/O error.
+    #[cfg(feature = "futures")]
+    pub async fn run_futures(
+        &mut self,
+        mut stream: impl futures::io::AsyncRead + futures::io::AsyncWrite + Unpin,
+    ) -> std::io::Result<()> {
+        use crate::runtime::handle_futures;
+
+        let mut input = None;
+
+        let result = loop {
+            match self.resume(input) {
+                Ok(result) => break result,
+                Err(io) => input = Some(handle_futures(&mut stream, io).await?),
+            }
+        };
+
+        Ok(result?)
+    }
+
+    /// Runs the coroutine to completion against the given async-std
+    /// stream.
+    ///
+    /// Async-std streams implement the [`futures`] I/O traits, so
+    /// this is an alias of [`Upgrade::run_futures`].
+    #[cfg(feature = "async-std")]
+    pub async fn run_async_std(
+        &mut self,
+        stream: impl async_std::io::Read + async_std::io::Write + Unpin,
+    ) -> std::io::Result<()> {
+        self.run_futures(stream).await
+    }
+
+    /// Runs the coroutine to completion against the given async
+    /// stream, then performs the TLS handshake using
+    /// [`tokio_native_tls`].
+    ///
+    /// Returns the secure stream, ready to be used.
+    #[cfg(feature = "tokio-native-tls")]
+    pub async fn run_native_tls<S>(
+        &mut self,
+        mut stream: S,
+        domain: &str,
+        connector: &tokio_native_tls::TlsConnector,
+    ) -> std::io::Result<tokio_native_tls::TlsStream<S>>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        self.run_tokio(&mut stream).await?;
+
+        connector
+            .connect(domain, stream)
+            .await
+            .map_err(std::io::Error::other)
+    }
+}
+
+/// The iterator running an [`Upgrade`] coroutine against a blocking
+/// stream.
+///
+/// See [`Upgrade::blocking_steps`].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct BlockingSteps<'a, P, S> {
+    upgrade: &'a mut Upgrade<P>,
+    stream: S,
+    input: Option<Io>,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<P: StartTls, S: std::io::Read + std::io::Write> Iterator for BlockingSteps<'_, P, S> {
+    type Item = std::io::Result<()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use io_stream::runtimes::std::handle;
+
+        if self.done {
+            return None;
+        }
+
+        let io = match self.upgrade.resume(self.input.take()) {
+            Ok(Ok(())) => {
+                self.done = true;
+                return None;
+            }
+            Ok(Err(err)) => {
+                self.done = true;
+                let err = std::io::Error::from(err);
+                return Some(Err(err));
+            }
+            Err(io) => io,
+        };
+
+        match handle(&mut self.stream, io) {
+            Ok(output) => {
+                self.input = Some(output);
+                Some(Ok(()))
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "io-stream")]
+/// Makes the given write coroutine progress.
+///
+/// The write coroutine may only write part of the command, in which
+/// case the remaining bytes are written again. Returns `true` once
+/// the whole command has been written. Like
+/// [`std::io::Write::write_all`], fails with
+/// [`UpgradeTlsError::WriteZero`] if no byte could be written, which
+/// would otherwise make the coroutine write the same bytes forever.
+pub(crate) fn flush(
+    write: &mut Write,
+    io: Option<Io>,
+) -> Result<Result<bool, UpgradeTlsError>, Io> {
+    let mut output = write.resume(io)?;
+
+    if output.bytes_count >= output.buffer.len() {
+        return Ok(Ok(true));
+    }
+
+    if output.bytes_count == 0 {
+        return Ok(Err(UpgradeTlsError::WriteZero));
+    }
+
+    // the written bytes are drained in place, so that large commands
+    // written a few bytes at a time are not copied on every write
+    output.buffer.drain(..output.bytes_count);
+
+    debug!(
+        "partially written command, {} bytes remaining",
+        output.buffer.len()
+    );
+    write.replace(output.buffer);
+
+    Ok(Ok(false))
+}
+
+/// Makes the given read coroutine progress, appending the bytes read
+/// to the given buffer.
+///
+/// This is meant for the coroutines reading a response outside of
+/// the [`Upgrade`] flow, which share its limits: fails with
+/// [`UpgradeTlsError::LineTooLong`] once the buffer reached
+/// [`DEFAULT_MAX_BUFFER`], or with [`UpgradeTlsError::UnexpectedEof`]
+/// if the server closed the connection, which would otherwise make
+/// the coroutine read forever.
+#[cfg(all(feature = "io-stream", any(feature = "imap", feature = "smtp")))]
+pub(crate) fn fill(
+    read: &mut Read,
+    bytes: &mut Vec<u8>,
+    io: Option<Io>,
+) -> Result<Result<(), UpgradeTlsError>, Io> {
+    if bytes.len() >= DEFAULT_MAX_BUFFER {
+        return Ok(Err(UpgradeTlsError::LineTooLong));
+    }
+
+    let output = read.resume(io)?;
+    let eof = output.bytes_count == 0;
+    bytes.extend(output.bytes());
+    read.replace(output.buffer);
+
+    if eof {
+        return Ok(Err(UpgradeTlsError::UnexpectedEof));
+    }
+
+    Ok(Ok(()))
+}
+
+/// Returns the index following the last complete line of the given
+/// bytes, for protocols whose completion checks consider lines one
+/// by one.
+#[cfg(any(feature = "sieve", feature = "smtp"))]
+pub(crate) fn drain_lines_offset(bytes: &[u8]) -> Option<usize> {
+    memchr::memrchr(b'\n', bytes).map(|n| n + 1)
+}
+
+/// Builds the result of the given FTP or SMTP reply, which succeeds
+/// if its 3-digit code starts with the given one (like `2` for any
+/// positive completion reply).
+#[cfg(any(feature = "ftp", feature = "smtp"))]
+pub(crate) fn reply_result(reply: &[u8], success: &[u8]) -> Result<(), UpgradeTlsError> {
+    let code = reply.get(..3).unwrap_or(reply);
+
+    if code.starts_with(success) {
+        return Ok(());
+    }
+
+    Err(UpgradeTlsError::Rejected {
+        status: String::from_utf8_lossy(code).into_owned(),
+        line: String::from_utf8_lossy(reply).trim_end().to_owned(),
+    })
+}
+
+#[cfg(feature = "io-stream")]
+impl<P: StartTls + Default> Default for Upgrade<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,104 @@
+//! Module dedicated to the [`UpgradeTls`] coroutine for the XMPP
+//! protocol.
+//!
+//! In XMPP, the greeting consists of the server stream header
+//! followed by the `<stream:features>` element, sent in response to
+//! the client stream header. The client stream header is not sent by
+//! the coroutine: it needs to be written beforehand.
+
+use alloc::string::String;
+
+use memchr::{memchr, memmem};
+
+#[cfg(feature = "io-stream")]
+use crate::Upgrade;
+use crate::{Completion, StartTls, UpgradeTlsError};
+
+/// The default port of the plain XMPP service.
+pub const DEFAULT_STARTTLS_PORT: u16 = 5222;
+
+/// The STARTTLS command written to the server.
+pub const STARTTLS_COMMAND: &[u8] = b"<starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>";
+
+/// The opening tag of the successful STARTTLS response element.
+const PROCEED: &[u8] = b"<proceed";
+
+/// The opening tag of the failed STARTTLS response element.
+const FAILURE: &[u8] = b"<failure";
+
+/// The STARTTLS coroutine that upgrades a plain XMPP (TCP) stream to
+/// a secure one.
+#[cfg(feature = "io-stream")]
+pub type UpgradeTls = Upgrade<Xmpp>;
+
+/// The XMPP flavor of the [`Upgrade`] coroutine.
+#[derive(Clone, Debug, Default)]
+pub struct Xmpp;
+
+impl StartTls for Xmpp {
+    fn command(&self) -> &[u8] {
+        STARTTLS_COMMAND
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        find_features_end(bytes).map(|n| n + 1)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let (start, end) = find_response(bytes)?;
+        let element = &bytes[start..=end];
+
+        if element.starts_with(PROCEED) {
+            return Some(Completion::new(end + 1, Ok(())));
+        }
+
+        let err = UpgradeTlsError::Rejected {
+            status: String::from("failure"),
+            line: String::from_utf8_lossy(element).into_owned(),
+        };
+
+        Some(Completion::new(end + 1, Err(err)))
+    }
+
+    /// The status is the name of the response element, either
+    /// `proceed` or `failure`.
+    fn status<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        for tag in [PROCEED, FAILURE] {
+            if memmem::find(line, tag).is_some() {
+                return &tag[1..];
+            }
+        }
+
+        &line[..0]
+    }
+}
+
+/// Finds the end of the `<stream:features>` element.
+///
+/// Returns the index of the `>` closing the element, either as
+/// `</stream:features>` or as the empty `<stream:features/>`.
+fn find_features_end(bytes: &[u8]) -> Option<usize> {
+    for tag in [&b"</stream:features>"[..], b"<stream:features/>"] {
+        if let Some(n) = memmem::find(bytes, tag) {
+            return Some(n + tag.len() - 1);
+        }
+    }
+
+    None
+}
+
+/// Finds the STARTTLS response element.
+///
+/// The response is either a `<proceed/>` or a `<failure/>` element.
+/// Returns the index of the `<` opening the element alongside the
+/// index of the `>` closing its opening tag.
+fn find_response(bytes: &[u8]) -> Option<(usize, usize)> {
+    for tag in [PROCEED, FAILURE] {
+        if let Some(n) = memmem::find(bytes, tag) {
+            let start = n + tag.len();
+            return memchr(b'>', &bytes[start..]).map(|m| (n, start + m));
+        }
+    }
+
+    None
+}
@@ -0,0 +1,132 @@
+//! Module dedicated to the in-memory test harness.
+//!
+//! The [`MockStream`] plays a scripted server: every read returns the
+//! next scripted chunk, while every write is recorded. This allows
+//! coroutines to be tested deterministically, without any socket.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Result, Write},
+};
+
+use io_starttls::{CompletionReport, Progress, StartTls, Upgrade, UpgradeTlsError};
+use io_stream::Io;
+
+/// The in-memory stream feeding scripted chunks to the coroutine.
+#[derive(Debug, Default)]
+pub struct MockStream {
+    chunks: VecDeque<Vec<u8>>,
+    written: Vec<u8>,
+}
+
+impl MockStream {
+    /// Creates a new stream from the given scripted chunks.
+    ///
+    /// Each chunk is returned by exactly one read, unless it does not
+    /// fit in the read buffer, in which case the rest of the chunk is
+    /// returned by the next read.
+    pub fn new<I, C>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<[u8]>,
+    {
+        Self {
+            chunks: chunks.into_iter().map(|c| c.as_ref().to_vec()).collect(),
+            written: Vec::new(),
+        }
+    }
+
+    /// Creates a new stream returning the given bytes one by one.
+    pub fn bytewise(bytes: impl AsRef<[u8]>) -> Self {
+        Self::new(bytes.as_ref().iter().map(|b| [*b]))
+    }
+
+    /// Returns all the bytes written so far.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// Returns `true` if all the scripted chunks have been read.
+    pub fn is_exhausted(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let Some(mut chunk) = self.chunks.pop_front() else {
+            panic!("unexpected read, no more scripted chunk");
+        };
+
+        if chunk.len() > buf.len() {
+            let rest = chunk.split_off(buf.len());
+            self.chunks.push_front(rest);
+        }
+
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        Ok(chunk.len())
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Processes the given I/O request against the given stream.
+pub fn handle(stream: &mut MockStream, io: Io) -> Io {
+    io_stream::runtimes::std::handle(stream, io).unwrap()
+}
+
+/// Resumes the given coroutine against the given stream, until it
+/// returns its result.
+///
+/// The closure resumes the coroutine with the given I/O result, which
+/// allows coroutines other than [`Upgrade`] to be driven.
+pub fn drive_with<T>(
+    stream: &mut MockStream,
+    mut resume: impl FnMut(Option<Io>) -> std::result::Result<T, Io>,
+) -> T {
+    let mut input = None;
+
+    loop {
+        match resume(input.take()) {
+            Ok(result) => break result,
+            Err(io) => input = Some(handle(stream, io)),
+        }
+    }
+}
+
+/// Runs the given coroutine to completion against the given stream.
+pub fn drive<P: StartTls>(
+    upgrade: &mut Upgrade<P>,
+    stream: &mut MockStream,
+) -> std::result::Result<(), UpgradeTlsError> {
+    drive_with(stream, |io| upgrade.resume(io))
+}
+
+/// Runs the given coroutine to completion against the given stream,
+/// see [`Upgrade::resume_progress`].
+pub fn drive_progress<P: StartTls>(
+    upgrade: &mut Upgrade<P>,
+    stream: &mut MockStream,
+) -> std::result::Result<Progress, UpgradeTlsError> {
+    drive_with(stream, |io| upgrade.resume_progress(io))
+}
+
+/// Runs the given coroutine to completion against the given stream,
+/// see [`Upgrade::resume_report`].
+pub fn drive_report<P: StartTls>(
+    upgrade: &mut Upgrade<P>,
+    stream: &mut MockStream,
+) -> std::result::Result<CompletionReport, UpgradeTlsError> {
+    drive_with(stream, |io| upgrade.resume_report(io))
+}
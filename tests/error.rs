@@ -0,0 +1,75 @@
+#![cfg(feature = "std")]
+
+use std::io;
+
+use io_starttls::UpgradeTlsError;
+
+#[test]
+fn display() {
+    let err = UpgradeTlsError::Rejected {
+        status: "NO".into(),
+        line: "NGC6543 NO not now".into(),
+    };
+
+    assert_eq!(
+        err.to_string(),
+        "server rejected STARTTLS: NGC6543 NO not now"
+    );
+
+    let err = UpgradeTlsError::InvalidResponse(b"garbage\r\n".to_vec());
+    assert_eq!(
+        err.to_string(),
+        "server sent an invalid response: \"garbage\\r\\n\""
+    );
+}
+
+#[test]
+fn into_io_error() {
+    let err = UpgradeTlsError::Rejected {
+        status: "NO".into(),
+        line: "NGC6543 NO not now".into(),
+    };
+
+    let err = io::Error::from(err);
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    assert_eq!(
+        err.to_string(),
+        "server rejected STARTTLS: NGC6543 NO not now"
+    );
+
+    let err = io::Error::from(UpgradeTlsError::LineTooLong);
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let err = io::Error::from(UpgradeTlsError::Timeout);
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    let err = io::Error::from(UpgradeTlsError::WriteZero);
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+
+    let err = io::Error::from(UpgradeTlsError::UnexpectedEof);
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let inner = err.into_inner().unwrap();
+    let inner = inner.downcast::<UpgradeTlsError>().unwrap();
+    assert_eq!(*inner, UpgradeTlsError::UnexpectedEof);
+}
+
+#[test]
+fn boxed_error() {
+    fn upgrade() -> Result<(), Box<dyn std::error::Error>> {
+        Err(UpgradeTlsError::StartTlsNotAdvertised)?
+    }
+
+    let err = upgrade().unwrap_err();
+    assert_eq!(err.to_string(), "server does not advertise STARTTLS");
+}
+
+#[test]
+fn io_result() {
+    fn connect() -> io::Result<()> {
+        Err(UpgradeTlsError::UnexpectedEof)?
+    }
+
+    let err = connect().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
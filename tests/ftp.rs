@@ -0,0 +1,52 @@
+#![cfg(feature = "ftp")]
+
+mod common;
+
+use common::{drive, MockStream};
+use io_starttls::{ftp::UpgradeTls, UpgradeTlsError};
+
+#[test]
+fn auth_tls() {
+    let mut stream = MockStream::new(["220 ready\r\n", "234 AUTH TLS successful\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"AUTH TLS\r\n");
+    assert_eq!(starttls.response(), Some("234 AUTH TLS successful"));
+}
+
+#[test]
+fn rejected() {
+    let mut stream = MockStream::new(["220 ready\r\n", "502 command not implemented\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let err = UpgradeTlsError::Rejected {
+        status: "502".into(),
+        line: "502 command not implemented".into(),
+    };
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn drain_lines_multiline_banner() {
+    // multiline replies are recognized by their first line, which
+    // must then be kept until the reply completes
+    let mut stream = MockStream::new([
+        "220-welcome\r\n",
+        "331 is not a code here\r\n",
+        "220 ready\r\n",
+        "234 AUTH TLS successful\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_drain_lines(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        starttls.greeting(),
+        Some("220-welcome\r\n331 is not a code here\r\n220 ready\r\n")
+    );
+    assert_eq!(starttls.response(), Some("234 AUTH TLS successful"));
+    assert!(stream.is_exhausted());
+}
@@ -0,0 +1,1445 @@
+#![cfg(feature = "imap")]
+
+mod common;
+
+use common::{drive, drive_progress, drive_report, drive_with, handle, MockStream};
+use io_starttls::{
+    imap::{self, Capability, Noop, ResponseCode, Status, UpgradeTls},
+    CompletionReport, Progress, StartTls, StartingState, Stats, UpgradeTlsError,
+    DEFAULT_MAX_BUFFER,
+};
+
+#[test]
+fn greeting_terminator_in_separate_read() {
+    let mut stream = MockStream::new([
+        "* OK IMAP4rev1 server ready\r",
+        "\n",
+        "NGC6543 OK begin TLS now\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+    assert_eq!(starttls.greeting(), Some("* OK IMAP4rev1 server ready\r\n"));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn greeting_split_across_reads() {
+    let mut stream = MockStream::new(["* O", "K ", "ready", "\r\n", "NGC6543 OK\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.greeting(), Some("* OK ready\r\n"));
+}
+
+#[test]
+fn response_byte_by_byte() {
+    let mut stream = MockStream::bytewise("* OK ready\r\nNGC6543 OK begin TLS now\r\n");
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert!(starttls.remaining().is_empty());
+}
+
+#[test]
+fn untagged_lines_before_tagged_response() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* OK [ALERT] NGC6543 is not the tag\r\n* 1 EXISTS\r\n",
+        "NGC6543 OK begin TLS now\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+}
+
+#[test]
+fn rejected_response() {
+    let mut stream = MockStream::new(["NGC6543 NO TLS unavailable\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::Rejected {
+        status: "NO".into(),
+        line: "NGC6543 NO TLS unavailable".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn greeting_capability_code_in_fragments() {
+    let mut stream = MockStream::new([
+        "* OK [CA",
+        "PABILITY IMAP4rev1 STARTTLS",
+        "] ready\r",
+        "\n",
+        "NGC6543 OK\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.capabilities(), ["IMAP4rev1", "STARTTLS"]);
+    assert_eq!(
+        starttls.greeting(),
+        Some("* OK [CAPABILITY IMAP4rev1 STARTTLS] ready\r\n")
+    );
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+}
+
+#[test]
+fn greeting_larger_than_read_capacity() {
+    let greeting = "* OK [CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED] ready\r\n";
+    let mut stream = MockStream::new([greeting, "NGC6543 OK\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_read_capacity(3);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.greeting(), Some(greeting));
+    assert!(starttls.remaining().is_empty());
+}
+
+#[test]
+fn greeting_and_response_in_same_read() {
+    let mut stream = MockStream::new(["* OK ready\r\nNGC6543 OK begin TLS now\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.greeting(), Some("* OK ready\r\n"));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+    assert!(starttls.remaining().is_empty());
+}
+
+#[test]
+fn greeting_followed_by_partial_response() {
+    let mut stream = MockStream::new(["* OK ready\r\nNGC65", "43 OK\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn buffered_response_without_greeting() {
+    let mut stream = MockStream::new(["OK\r\n"]);
+    let mut starttls = UpgradeTls::new().with_buffer(b"NGC6543 ".to_vec());
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+}
+
+#[test]
+fn progress() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* CAPABILITY IMAP4rev1 STARTTLS\r\nNGC6543 OK done\r\n",
+        "NGC6543 OK begin TLS now\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    let progress = drive_progress(&mut starttls, &mut stream).unwrap();
+
+    assert_eq!(
+        progress,
+        Progress {
+            greeting_discarded: true,
+            prelude_commands: 1,
+            response: "NGC6543 OK begin TLS now".into(),
+            bytes_consumed: 12 + 50 + 26,
+        }
+    );
+}
+
+#[test]
+fn report() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* CAPABILITY IMAP4rev1 STARTTLS\r\nNGC6543 OK done\r\n",
+        "NGC6543 OK begin TLS now\r\nextra",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    let report = drive_report(&mut starttls, &mut stream).unwrap();
+
+    assert_eq!(
+        report,
+        CompletionReport {
+            greeting: Some("* OK ready\r\n".into()),
+            response: "NGC6543 OK begin TLS now".into(),
+            status: io_starttls::Status::Keyword("OK".into()),
+            trailing_bytes: 5,
+            capabilities: vec!["IMAP4rev1".into(), "STARTTLS".into()],
+        }
+    );
+}
+
+#[test]
+fn report_rejected() {
+    let mut stream = MockStream::new(["NGC6543 NO denied\r\n"]);
+    let mut starttls = UpgradeTls::new();
+    let result = drive_report(&mut starttls, &mut stream);
+
+    assert!(matches!(result, Err(UpgradeTlsError::Rejected { .. })));
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn events() {
+    use futures::{executor::block_on, StreamExt};
+    use io_starttls::Event;
+
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 OK begin TLS now\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+    let events = starttls.events();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    drop(starttls);
+
+    assert_eq!(
+        block_on(events.collect::<Vec<_>>()),
+        [
+            Event::GreetingDiscarded { bytes: 12 },
+            Event::CommandWritten { prelude: false },
+            Event::ResponseReceived {
+                prelude: false,
+                accepted: true,
+            },
+        ]
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn observer() {
+    use std::sync::{Arc, Mutex};
+
+    use io_starttls::Event;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 NO nope\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_observer({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event)
+        });
+
+    assert!(drive(&mut starttls, &mut stream).is_err());
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            Event::GreetingDiscarded { bytes: 12 },
+            Event::CommandWritten { prelude: false },
+            Event::ResponseReceived {
+                prelude: false,
+                accepted: false,
+            },
+        ]
+    );
+}
+
+#[test]
+fn connection_closed_before_response() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC65", ""]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let err = UpgradeTlsError::UnexpectedEof;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn connection_closed_after_tag_without_newline() {
+    for chunk in ["NGC6543 OK begin TLS", "NGC6543 OK begin TLS\r", "NGC6543 "] {
+        let mut stream = MockStream::new(["* OK ready\r\n", chunk, ""]);
+        let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+        let err = UpgradeTlsError::UnexpectedEof;
+        assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+        assert!(stream.is_exhausted());
+    }
+}
+
+#[test]
+fn connection_closed_within_literal() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "* 1 FETCH {99}\r\nNGC6543 OK\r\n", ""]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let err = UpgradeTlsError::UnexpectedEof;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn literal_length_overflow() {
+    let line = format!("* OK {{{}}}\r\n", usize::MAX);
+    let mut stream = MockStream::new([line.clone(), "NGC6543 OK\r\n".into()]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::InvalidResponse(line.into_bytes());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn literal_larger_than_max_buffer() {
+    let mut stream = MockStream::new(["* 1 FETCH {1025}\r\n"]);
+    let mut starttls = UpgradeTls::new().with_max_buffer(1024);
+
+    let err = UpgradeTlsError::LineTooLong;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn buffered_reader_bytes() {
+    let mut stream = MockStream::new(["OK\r\n"]);
+    let mut starttls = UpgradeTls::new().with_strict_buffer(true);
+
+    // bytes already pulled by a buffered reader wrapping the stream
+    starttls.extend_buffer(b"NGC6543 ");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.check_remaining(b""), Ok(()));
+
+    let err = UpgradeTlsError::TrailingBytes(b"injected".to_vec());
+    assert_eq!(starttls.check_remaining(b"injected"), Err(err));
+}
+
+#[test]
+fn skip_command() {
+    let mut stream = MockStream::new(["* OK still there\r\nA042 OK begin TLS now\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_skip_command(true)
+        .with_tag("A042");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert!(stream.written().is_empty());
+    assert_eq!(starttls.greeting(), None);
+}
+
+#[test]
+fn prelude_with_own_tag() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* ID (\"name\" \"Dovecot\")\r\nA001 OK ID completed\r\n",
+        "NGC6543 OK begin TLS now\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_prelude(vec![b"A001 ID NIL\r\n".to_vec()]);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"A001 ID NIL\r\nNGC6543 STARTTLS\r\n");
+}
+
+#[test]
+fn custom_command() {
+    let mut stream = MockStream::new(["A OK begin TLS now\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_command("A STARTTLS\n")
+        .with_response_marker("A ");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"A STARTTLS\n");
+}
+
+#[test]
+fn response_line() {
+    let mut stream =
+        MockStream::new(["* OK still there\r\nNGC6543 OK Begin TLS negotiation now\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    assert_eq!(starttls.response(), None);
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        starttls.response(),
+        Some("NGC6543 OK Begin TLS negotiation now")
+    );
+}
+
+#[test]
+fn untagged_response() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "* OK still there\r\nOK\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_untagged_response(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response(), Some("OK"));
+}
+
+#[test]
+fn untagged_response_times_out_by_default() {
+    let mut stream = MockStream::new(["OK\r\n", "\r\n"]);
+    let mut starttls = UpgradeTls::new().with_max_reads(2);
+
+    let err = UpgradeTlsError::Timeout;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn transcript() {
+    let mut stream = MockStream::new(["* OK re", "ady\r\n", "* 1 EXISTS\r\nNGC6543 OK\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_transcript(true);
+
+    assert!(starttls.transcript().is_empty());
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        starttls.transcript(),
+        b"S: * OK ready\r\nC: NGC6543 STARTTLS\r\nS: * 1 EXISTS\r\nS: NGC6543 OK\r\n"
+    );
+}
+
+#[test]
+fn greeting_too_long() {
+    let mut stream = MockStream::new(["* OK gree", "ting"]);
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_max_buffer(8);
+
+    let err = UpgradeTlsError::LineTooLong;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn response_too_long() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "* STREAMING", "\r\nNGC6543 OK\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_max_buffer(8);
+
+    let err = UpgradeTlsError::LineTooLong;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn starting_state() {
+    let starttls = UpgradeTls::new();
+    assert_eq!(starttls.starting_state(), Ok(StartingState::WriteCommand));
+
+    let starttls = UpgradeTls::new().with_discard_greeting(true);
+    assert_eq!(
+        starttls.starting_state(),
+        Ok(StartingState::DiscardGreeting)
+    );
+
+    let starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_skip_command(true);
+    assert_eq!(
+        starttls.starting_state(),
+        Ok(StartingState::DiscardResponse)
+    );
+
+    let starttls = UpgradeTls::new().with_starting_state(StartingState::DiscardGreeting);
+    assert_eq!(
+        starttls.starting_state(),
+        Ok(StartingState::DiscardGreeting)
+    );
+}
+
+#[test]
+fn skip_command_with_check_capability() {
+    let mut stream = MockStream::new::<_, &str>([]);
+    let mut starttls = UpgradeTls::new()
+        .with_skip_command(true)
+        .with_check_capability(true);
+
+    let reason = "prelude commands cannot be written when skipping the command";
+    let err = UpgradeTlsError::InvalidConfig(reason.into());
+    assert_eq!(starttls.starting_state(), Err(err.clone()));
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn command_written_without_discarding_greeting() {
+    let mut stream = MockStream::new(["NGC6543 OK begin TLS now\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(false);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+    assert_eq!(starttls.greeting(), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn blocking_steps() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 OK\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let steps = starttls.blocking_steps(&mut stream).count();
+    assert_eq!(steps, 3);
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn blocking_steps_rejected() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 NO\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let result: std::io::Result<Vec<()>> = starttls.blocking_steps(&mut stream).collect();
+    assert!(result.is_err());
+}
+
+#[test]
+fn capability_after_upgrade() {
+    let mut stream = MockStream::new([
+        "* CAPABILITY IMAP4rev1 AUTH=PLAIN\r\n",
+        "A OK CAPABILITY completed\r\n",
+    ]);
+
+    let mut capability = imap::capability();
+    let capabilities = drive_with(&mut stream, |io| capability.resume(io));
+
+    let expected = vec!["IMAP4rev1".to_owned(), "AUTH=PLAIN".to_owned()];
+    assert_eq!(capabilities, Ok(expected));
+    assert_eq!(stream.written(), b"A CAPABILITY\r\n");
+}
+
+#[test]
+fn capability_rejected() {
+    let mut stream = MockStream::new(["A042 BAD unknown command\r\n"]);
+
+    let mut capability = Capability::new("A042");
+    let result = drive_with(&mut stream, |io| capability.resume(io));
+
+    let err = UpgradeTlsError::Rejected {
+        status: "BAD".into(),
+        line: "A042 BAD unknown command".into(),
+    };
+
+    assert_eq!(result, Err(err));
+}
+
+#[test]
+fn capability_connection_closed() {
+    let mut stream = MockStream::new(["* CAPABILITY IMAP4rev1\r\n", ""]);
+
+    let mut capability = imap::capability();
+    let result = drive_with(&mut stream, |io| capability.resume(io));
+
+    assert_eq!(result, Err(UpgradeTlsError::UnexpectedEof));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn capability_bye() {
+    let mut stream = MockStream::new(["* BYE shutting down\r\n"]);
+
+    let mut capability = imap::capability();
+    let result = drive_with(&mut stream, |io| capability.resume(io));
+
+    let err = UpgradeTlsError::ServerClosing("* BYE shutting down".into());
+    assert_eq!(result, Err(err));
+}
+
+#[test]
+fn capability_response_too_long() {
+    let mut stream = MockStream::new([vec![b'x'; DEFAULT_MAX_BUFFER + 1]]);
+
+    let mut capability = imap::capability();
+    let result = drive_with(&mut stream, |io| capability.resume(io));
+
+    assert_eq!(result, Err(UpgradeTlsError::LineTooLong));
+}
+
+#[test]
+fn strict_ascii_greeting() {
+    let mut stream = MockStream::new(["* OK r\u{e9}ady\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_strict_ascii(true);
+
+    let err = UpgradeTlsError::NonAscii("* OK r\u{e9}ady\r\n".into());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert!(stream.written().is_empty());
+}
+
+#[test]
+fn strict_ascii_response() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 OK \u{e9}\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_strict_ascii(true);
+
+    let err = UpgradeTlsError::NonAscii("NGC6543 OK \u{e9}\r\n".into());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn trailing_handler() {
+    use std::sync::{Arc, Mutex};
+
+    let trailing = Arc::new(Mutex::new(Vec::new()));
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 OK\r\ninjected"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_trailing_handler({
+            let trailing = trailing.clone();
+            move |bytes| trailing.lock().unwrap().push(bytes.to_vec())
+        });
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(*trailing.lock().unwrap(), [b"injected".to_vec()]);
+}
+
+#[test]
+fn capability_code_in_response() {
+    let mut stream = MockStream::new([
+        "* OK [CAPABILITY IMAP4rev1 STARTTLS] ready\r\n",
+        "NGC6543 OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] Begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.capabilities(), ["IMAP4rev1", "AUTH=PLAIN"]);
+}
+
+#[test]
+fn capability_code_in_rejected_response() {
+    let mut stream = MockStream::new(["NGC6543 NO [CAPABILITY IMAP4rev1] nope\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    assert!(drive(&mut starttls, &mut stream).is_err());
+    assert!(starttls.capabilities().is_empty());
+}
+
+#[test]
+fn untagged_lines_across_reads() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* 1 EXISTS\r\n* 2 EXI",
+        "STS\r\nNGC65",
+        "43 OK begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS"));
+    assert!(starttls.remaining().is_empty());
+}
+
+#[test]
+fn drain_untagged_lines() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* 1 EXISTS\r\n* 2 EXI",
+        "STS\r\nNGC65",
+        "43 OK begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_drain_lines(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS"));
+}
+
+#[test]
+fn drain_lines_with_literal() {
+    // the literal holds a line looking like the tagged response, which
+    // must not be drained away from its announcing line
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* 1 FETCH (BODY[] {19}\r\n",
+        "NGC6543 OK fake\r\nab)\r\n",
+        "NGC6543 OK begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_drain_lines(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS"));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn bye_response() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "* BYE shutting down\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let err = UpgradeTlsError::ServerClosing("* BYE shutting down".into());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn bye_prelude_response() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "* BYE\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    let err = UpgradeTlsError::ServerClosing("* BYE".into());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn quiet() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* 1 EXISTS\r\n",
+        "NGC6543 OK begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_quiet(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.greeting(), Some("* OK ready\r\n"));
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS"));
+}
+
+#[test]
+fn for_connected() {
+    let mut stream = MockStream::new(["NGC6543 OK begin TLS\r\n"]);
+    let mut starttls = UpgradeTls::for_connected();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.greeting(), None);
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+}
+
+#[test]
+fn for_connected_with_pre_read_greeting() {
+    let mut stream = MockStream::new(["NGC6543 OK begin TLS\r\n"]);
+    let mut starttls = UpgradeTls::for_connected()
+        .with_buffer(b"* OK ready\r\n".to_vec())
+        .with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.greeting(), Some("* OK ready\r\n"));
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS"));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn response_codes() {
+    let mut stream = MockStream::new(["NGC6543 OK [PRIVACYREQUIRED] begin TLS\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response_codes(), [ResponseCode::PrivacyRequired]);
+
+    let mut stream = MockStream::new(["NGC6543 OK [ALERT] Upgrade soon \r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    let code = ResponseCode::Alert("Upgrade soon".into());
+    assert_eq!(starttls.response_codes(), [code]);
+
+    let mut stream = MockStream::new(["NGC6543 OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] go\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    let code = ResponseCode::Capability(vec!["IMAP4rev1".into(), "AUTH=PLAIN".into()]);
+    assert_eq!(starttls.response_codes(), [code]);
+
+    let mut stream = MockStream::new(["NGC6543 OK [UIDVALIDITY 42] go\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    let code = ResponseCode::Other("UIDVALIDITY 42".into());
+    assert_eq!(starttls.response_codes(), [code]);
+}
+
+#[test]
+fn no_response_codes() {
+    let mut stream = MockStream::new(["NGC6543 OK begin [TLS] now\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert!(starttls.response_codes().is_empty());
+
+    let mut stream = MockStream::new(["NGC6543 NO [PRIVACYREQUIRED] nope\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    assert!(drive(&mut starttls, &mut stream).is_err());
+    assert!(starttls.response_codes().is_empty());
+}
+
+#[test]
+fn stats() {
+    let mut stream = MockStream::new([
+        "* OK IMAP4rev1 server ready\r",
+        "\n",
+        "NGC6543 OK begin TLS now\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+    assert_eq!(starttls.stats(), Stats::default());
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        starttls.stats(),
+        Stats {
+            reads: 3,
+            writes: 1
+        }
+    );
+
+    starttls.reset();
+    assert_eq!(starttls.stats(), Stats::default());
+}
+
+#[test]
+fn reset_replays_like_fresh_coroutine() {
+    let chunks = [
+        "* OK ready\r\n",
+        "* CAPABILITY IMAP4rev1 STARTTLS\r\nNGC6543 OK done\r\n",
+        "NGC6543 OK begin TLS\r\n",
+    ];
+
+    let prototype = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true)
+        .with_transcript(true)
+        .with_read_capacity(16);
+
+    let mut fresh = prototype.clone();
+    let mut fresh_stream = MockStream::new(chunks);
+    assert_eq!(drive(&mut fresh, &mut fresh_stream), Ok(()));
+
+    // interrupts a first flow while a read is pending
+    let mut recycled = prototype;
+    let mut stream = MockStream::new(chunks);
+    let io = recycled.resume(None).unwrap_err();
+    let io = handle(&mut stream, io);
+    let io = recycled.resume(Some(io)).unwrap_err();
+    assert!(matches!(io, io_stream::Io::Write(_)));
+
+    recycled.reset();
+
+    let mut stream = MockStream::new(chunks);
+    assert_eq!(drive(&mut recycled, &mut stream), Ok(()));
+    assert_eq!(stream.written(), fresh_stream.written());
+    assert_eq!(recycled.greeting(), fresh.greeting());
+    assert_eq!(recycled.response(), fresh.response());
+    assert_eq!(recycled.capabilities(), fresh.capabilities());
+    assert_eq!(recycled.transcript(), fresh.transcript());
+    assert_eq!(recycled.stats(), fresh.stats());
+
+    // also interrupts a flow right after a read request
+    let mut stream = MockStream::new(chunks);
+    let _ = recycled.resume(None).unwrap_err();
+    recycled.reset();
+
+    assert_eq!(drive(&mut recycled, &mut stream), Ok(()));
+    assert_eq!(stream.written(), fresh_stream.written());
+    assert_eq!(recycled.transcript(), fresh.transcript());
+}
+
+#[test]
+fn untagged_literal() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* 1 FETCH (BODY[] {19}\r\nNGC6543 OK fake\r\n\r\n)\r\n",
+        "NGC6543 OK begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS"));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn untagged_literals_across_reads() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* 1 FETCH (BODY[1] {4+}\r\nNGC6",
+        "543 BODY[2] {10}\r\nNGC6543 NO",
+        ")\r\nNGC6543 OK begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS"));
+    assert!(starttls.remaining().is_empty());
+}
+
+#[test]
+fn noop_after_upgrade() {
+    let mut stream = MockStream::new(["* 2 EXISTS\r\n", "A OK NOOP completed\r\n"]);
+
+    let mut noop = imap::noop();
+    let status = drive_with(&mut stream, |io| noop.resume(io));
+
+    assert_eq!(status, Ok(Status::Ok));
+    assert_eq!(stream.written(), b"A NOOP\r\n");
+}
+
+#[test]
+fn noop_statuses() {
+    for (response, expected) in [
+        ("A7 NO not now\r\n", Ok(Status::No)),
+        ("A7 BAD unknown command\r\n", Ok(Status::Bad)),
+        (
+            "A7 WAT\r\n",
+            Err(UpgradeTlsError::InvalidResponse(b"A7 WAT\r\n".to_vec())),
+        ),
+        (
+            "* BYE\r\n",
+            Err(UpgradeTlsError::ServerClosing("* BYE".into())),
+        ),
+    ] {
+        let mut stream = MockStream::new([response]);
+
+        let mut noop = Noop::new("A7");
+        let status = drive_with(&mut stream, |io| noop.resume(io));
+
+        assert_eq!(status, expected);
+    }
+}
+
+#[test]
+fn noop_connection_closed() {
+    let mut stream = MockStream::new(["* 2 EXISTS\r\n", ""]);
+
+    let mut noop = imap::noop();
+    let status = drive_with(&mut stream, |io| noop.resume(io));
+
+    assert_eq!(status, Err(UpgradeTlsError::UnexpectedEof));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn noop_response_too_long() {
+    let mut stream = MockStream::new([vec![b'x'; DEFAULT_MAX_BUFFER + 1]]);
+
+    let mut noop = imap::noop();
+    let status = drive_with(&mut stream, |io| noop.resume(io));
+
+    assert_eq!(status, Err(UpgradeTlsError::LineTooLong));
+}
+
+#[test]
+fn already_secure() {
+    let mut stream = MockStream::new(["NGC6543 BAD TLS already active\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::AlreadySecure("NGC6543 BAD TLS already active".into());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn success_token() {
+    let mut stream = MockStream::new(["NGC6543 DONE begin TLS\r\n"]);
+    let mut starttls = UpgradeTls::new().with_success_token("done");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+
+    let mut stream = MockStream::new(["NGC6543 OK begin TLS\r\n"]);
+    let mut starttls = UpgradeTls::new().with_success_token("DONE");
+
+    let err = UpgradeTlsError::Rejected {
+        status: "OK".into(),
+        line: "NGC6543 OK begin TLS".into(),
+    };
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+
+    let mut stream = MockStream::new(["* BYE\r\n"]);
+    let mut starttls = UpgradeTls::new().with_success_token("BYE");
+
+    let err = UpgradeTlsError::ServerClosing("* BYE".into());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn upgrade_blocking_io() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 OK begin TLS\r\n"]);
+
+    imap::upgrade_blocking_io(&mut stream).unwrap();
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+    assert!(stream.is_exhausted());
+
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 NO nope\r\n"]);
+
+    let err = imap::upgrade_blocking_io(&mut stream).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn command_bytes() {
+    let starttls = UpgradeTls::new();
+    assert_eq!(starttls.command_bytes(), b"NGC6543 STARTTLS\r\n");
+
+    let starttls = UpgradeTls::new().with_tag("A042");
+    assert_eq!(starttls.command_bytes(), b"A042 STARTTLS\r\n");
+
+    let starttls = UpgradeTls::new().with_command(b"X STARTTLS\r\n".to_vec());
+    assert_eq!(starttls.command_bytes(), b"X STARTTLS\r\n");
+
+    let mut stream = MockStream::new(["A042 OK begin TLS\r\n"]);
+    let mut starttls = UpgradeTls::new().with_tag("A042");
+    let command = starttls.command_bytes().to_vec();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), command);
+}
+
+#[test]
+fn tag_with_line_terminators() {
+    let mut stream = MockStream::new::<_, &str>([]);
+    let mut starttls = UpgradeTls::new().with_tag("A1 LOGOUT\r\nA2");
+
+    let reason = "IMAP tag \"A1 LOGOUT\\r\\nA2\" contains line terminators".to_owned();
+    let err = UpgradeTlsError::InvalidConfig(reason);
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert!(stream.written().is_empty());
+
+    let mut stream = MockStream::new::<_, &str>([]);
+    let mut starttls = UpgradeTls::new()
+        .with_check_capability(true)
+        .with_tag_fn(|| "A1\n".to_owned());
+
+    assert!(matches!(
+        drive(&mut starttls, &mut stream),
+        Err(UpgradeTlsError::InvalidConfig(_))
+    ));
+    assert!(stream.written().is_empty());
+}
+
+#[test]
+fn starting_state_invalid_tag() {
+    for tag in ["A1\r\nA2", "A 1", ""] {
+        let starttls = UpgradeTls::new().with_tag(tag);
+        assert!(matches!(
+            starttls.starting_state(),
+            Err(UpgradeTlsError::InvalidConfig(_))
+        ));
+    }
+
+    let mut stream = MockStream::new::<_, &str>([]);
+    let mut starttls = UpgradeTls::new().with_tag("A 1");
+
+    let reason = "IMAP tag \"A 1\" is empty or contains spaces".to_owned();
+    let err = UpgradeTlsError::InvalidConfig(reason);
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert!(stream.written().is_empty());
+
+    let starttls = UpgradeTls::new().with_tag("A1");
+    assert_eq!(starttls.starting_state(), Ok(StartingState::WriteCommand));
+}
+
+#[test]
+fn invalid_command_tag() {
+    // the coroutines fail before requesting any I/O
+    let mut capability = Capability::new("A1 LOGOUT\r\nA2");
+    assert!(matches!(
+        capability.resume(None),
+        Ok(Err(UpgradeTlsError::InvalidConfig(_)))
+    ));
+
+    let mut noop = Noop::new("A 1");
+    assert!(matches!(
+        noop.resume(None),
+        Ok(Err(UpgradeTlsError::InvalidConfig(_)))
+    ));
+}
+
+#[test]
+fn command_with_line_terminators() {
+    let starttls = UpgradeTls::new().with_command(b"A LOGOUT\r\nA STARTTLS\r\n".to_vec());
+    assert!(matches!(
+        starttls.starting_state(),
+        Err(UpgradeTlsError::InvalidConfig(_))
+    ));
+
+    let starttls = UpgradeTls::new().with_prelude(vec![b"A NOOP\nA LOGOUT\n".to_vec()]);
+    assert!(matches!(
+        starttls.starting_state(),
+        Err(UpgradeTlsError::InvalidConfig(_))
+    ));
+
+    let starttls = UpgradeTls::new().with_command(b"A STARTTLS\n".to_vec());
+    assert_eq!(starttls.starting_state(), Ok(StartingState::WriteCommand));
+}
+
+#[test]
+fn starttls_command_constant() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 OK begin TLS now\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), imap::STARTTLS_COMMAND);
+    assert_eq!(starttls.protocol().tag(), Some(imap::DEFAULT_TAG));
+}
+
+#[test]
+fn capture_greeting() {
+    let mut stream = MockStream::new([
+        "* OK [CAPABILITY IMAP4rev1 STARTTLS] Dovecot ready\r\n",
+        "NGC6543 OK\r\n",
+    ]);
+    let mut starttls = UpgradeTls::new().with_capture_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+    assert_eq!(
+        starttls.greeting(),
+        Some("* OK [CAPABILITY IMAP4rev1 STARTTLS] Dovecot ready\r\n")
+    );
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn retries_transient_rejection() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "NGC6543 NO [UNAVAILABLE] try again\r\n",
+        "NGC6543.1 OK begin TLS now\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_retries(1);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        stream.written(),
+        b"NGC6543 STARTTLS\r\nNGC6543.1 STARTTLS\r\n"
+    );
+    assert_eq!(starttls.response(), Some("NGC6543.1 OK begin TLS now"));
+    assert!(stream.is_exhausted());
+
+    // the configured tag is restored for the next flow
+    starttls.reset();
+    assert_eq!(starttls.command_bytes(), b"NGC6543 STARTTLS\r\n");
+}
+
+#[test]
+fn retries_with_tag_fn() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "A001 NO [UNAVAILABLE] try again\r\n",
+        "A002 OK begin TLS now\r\n",
+    ]);
+
+    let mut counter = 0;
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_retries(1)
+        .with_tag_fn(move || {
+            counter += 1;
+            format!("A{counter:03}")
+        });
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"A001 STARTTLS\r\nA002 STARTTLS\r\n");
+}
+
+#[test]
+fn retries_skip_reply_code_tag() {
+    // the tag is not a status, so this rejection is permanent
+    let mut stream = MockStream::new(["* OK ready\r\n", "400 NO not allowed\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_tag("400")
+        .with_retries(1);
+
+    assert!(matches!(
+        drive(&mut starttls, &mut stream),
+        Err(UpgradeTlsError::Rejected { .. })
+    ));
+    assert_eq!(stream.written(), b"400 STARTTLS\r\n");
+}
+
+#[test]
+fn retries_exhausted() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "NGC6543 NO [UNAVAILABLE] try again\r\n",
+        "NGC6543.1 NO [UNAVAILABLE] still busy\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_retries(1);
+
+    assert_eq!(
+        drive(&mut starttls, &mut stream),
+        Err(UpgradeTlsError::Rejected {
+            status: "NO".into(),
+            line: "NGC6543.1 NO [UNAVAILABLE] still busy".into(),
+        })
+    );
+    assert_eq!(
+        stream.written(),
+        b"NGC6543 STARTTLS\r\nNGC6543.1 STARTTLS\r\n"
+    );
+}
+
+#[test]
+fn retries_skip_permanent_rejection() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 BAD unknown command\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_retries(3);
+
+    assert!(matches!(
+        drive(&mut starttls, &mut stream),
+        Err(UpgradeTlsError::Rejected { .. })
+    ));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+}
+
+#[test]
+fn retry_predicate() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "NGC6543 NO busy\r\n",
+        "NGC6543.1 OK begin TLS now\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_retries(1)
+        .with_retry_predicate(|line| line.ends_with("busy"));
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        stream.written(),
+        b"NGC6543 STARTTLS\r\nNGC6543.1 STARTTLS\r\n"
+    );
+}
+
+#[test]
+fn lowercase_status() {
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 ok begin TLS now\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+}
+
+#[test]
+fn large_command_written_in_small_chunks() {
+    use io_stream::{Io, Output};
+
+    let mut command = vec![b'x'; 4096];
+    command.extend_from_slice(b"\r\n");
+
+    let mut stream = MockStream::new(["NGC6543 OK begin TLS now\r\n"]);
+    let mut starttls = UpgradeTls::new().with_command(command.clone());
+    let mut written = Vec::new();
+    let mut input = None;
+
+    let result = loop {
+        match starttls.resume(input.take()) {
+            Ok(result) => break result,
+            // the server only accepts a few bytes per write
+            Err(Io::Write(Err(buffer))) => {
+                let bytes_count = buffer.len().min(5);
+                written.extend_from_slice(&buffer[..bytes_count]);
+                input = Some(Io::Write(Ok(Output {
+                    buffer,
+                    bytes_count,
+                })));
+            }
+            Err(io) => {
+                // the response is only read once the whole command
+                // has been written
+                assert_eq!(written, command);
+                input = Some(handle(&mut stream, io));
+            }
+        }
+    };
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(written, command);
+    assert_eq!(starttls.stats().writes, command.len().div_ceil(5));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn write_zero() {
+    use io_stream::{Io, Output};
+
+    let mut starttls = UpgradeTls::new();
+    let mut input = None;
+
+    let result = loop {
+        match starttls.resume(input.take()) {
+            Ok(result) => break result,
+            // the stream accepts no byte at all
+            Err(Io::Write(Err(buffer))) => {
+                input = Some(Io::Write(Ok(Output {
+                    buffer,
+                    bytes_count: 0,
+                })));
+            }
+            Err(io) => panic!("unexpected I/O request {io:?}"),
+        }
+    };
+
+    assert_eq!(result, Err(UpgradeTlsError::WriteZero));
+    assert_eq!(starttls.stats().writes, 1);
+}
+
+#[test]
+fn from_parts_discard_response() {
+    let mut stream = MockStream::new(["K begin TLS now\r\n"]);
+    let buffer = b"* OK still there\r\nNGC6543 O".to_vec();
+    let mut starttls = UpgradeTls::from_parts(StartingState::DiscardResponse, buffer);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"");
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS now"));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn from_parts_discard_greeting() {
+    let mut stream = MockStream::new(["NGC6543 OK begin TLS now\r\n"]);
+    let buffer = b"* OK ready\r\n".to_vec();
+    let mut starttls = UpgradeTls::from_parts(StartingState::DiscardGreeting, buffer);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+    assert_eq!(starttls.greeting(), Some("* OK ready\r\n"));
+}
+
+#[test]
+fn starttls_not_advertised_in_greeting() {
+    let mut stream = MockStream::new(["* OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] ready\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    assert_eq!(
+        drive(&mut starttls, &mut stream),
+        Err(UpgradeTlsError::StartTlsNotAdvertised)
+    );
+    assert_eq!(stream.written(), b"");
+}
+
+#[test]
+fn starttls_not_advertised_in_capability_response() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* CAPABILITY IMAP4rev1 AUTH=PLAIN\r\nNGC6543 OK done\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    assert_eq!(
+        drive(&mut starttls, &mut stream),
+        Err(UpgradeTlsError::StartTlsNotAdvertised)
+    );
+    assert_eq!(stream.written(), b"NGC6543 CAPABILITY\r\n");
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn tls_required() {
+    let vectors = [
+        (
+            "* OK [CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED] ready\r\n",
+            Some(true),
+        ),
+        (
+            "* OK [CAPABILITY IMAP4rev1 STARTTLS AUTH=PLAIN] ready\r\n",
+            Some(false),
+        ),
+        ("* OK [PRIVACYREQUIRED] ready\r\n", Some(true)),
+        ("* OK ready\r\n", None),
+    ];
+
+    for (greeting, required) in vectors {
+        let mut stream = MockStream::new([greeting, "NGC6543 OK begin TLS now\r\n"]);
+        let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+        assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+        assert_eq!(starttls.tls_required(), required, "{greeting}");
+    }
+}
+
+#[test]
+fn tls_required_from_capability_response() {
+    let mut stream = MockStream::new([
+        "* OK ready\r\n",
+        "* CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED\r\nNGC6543 OK done\r\n",
+        "NGC6543 OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] begin TLS now\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    // the capabilities of the response are the upgraded ones
+    assert_eq!(starttls.tls_required(), Some(true));
+
+    starttls.reset();
+    assert_eq!(starttls.tls_required(), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn no_greeting_over_tcp() {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // the server sends nothing until it receives the command
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut command = String::new();
+        BufReader::new(&mut stream).read_line(&mut command).unwrap();
+        stream.write_all(b"NGC6543 OK begin TLS now\r\n").unwrap();
+        command
+    });
+
+    let mut tcp = TcpStream::connect(addr).unwrap();
+    tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(false);
+    starttls.run_blocking(&mut tcp).unwrap();
+
+    assert_eq!(server.join().unwrap(), "NGC6543 STARTTLS\r\n");
+    assert_eq!(starttls.greeting(), None);
+    assert_eq!(starttls.response(), Some("NGC6543 OK begin TLS now"));
+}
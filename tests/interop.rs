@@ -0,0 +1,189 @@
+//! Interoperability test vectors.
+//!
+//! Every vector holds the bytes sent by a real-world server during
+//! the STARTTLS flow, modelled on the banners and replies of common
+//! server software (host names and identifiers are anonymized). Each
+//! vector is run twice: once with the server answering every command
+//! in a single read, once byte by byte.
+
+mod common;
+
+#[allow(unused_imports)]
+use common::{drive, MockStream};
+
+/// The STARTTLS flow of a real-world server.
+#[allow(dead_code)]
+struct Vector {
+    /// The server software.
+    server: &'static str,
+    /// The bytes sent by the server, one item per command answered.
+    script: &'static [&'static str],
+    /// The bytes the client is expected to write.
+    written: &'static str,
+    /// The expected STARTTLS response line.
+    response: &'static str,
+}
+
+#[allow(dead_code)]
+impl Vector {
+    /// Returns the streams replaying the vector, read by read then
+    /// byte by byte.
+    fn streams(&self) -> [MockStream; 2] {
+        [
+            MockStream::new(self.script),
+            MockStream::bytewise(self.script.concat()),
+        ]
+    }
+}
+
+#[cfg(feature = "imap")]
+const IMAP: &[Vector] = &[
+    Vector {
+        server: "Dovecot",
+        script: &[
+            "* OK [CAPABILITY IMAP4rev1 SASL-IR LOGIN-REFERRALS ID ENABLE IDLE LITERAL+ STARTTLS LOGINDISABLED] Dovecot (Debian) ready.\r\n",
+            "NGC6543 OK Begin TLS negotiation now.\r\n",
+        ],
+        written: "NGC6543 STARTTLS\r\n",
+        response: "NGC6543 OK Begin TLS negotiation now.",
+    },
+    Vector {
+        server: "Cyrus",
+        script: &[
+            "* OK [CAPABILITY IMAP4rev1 LITERAL+ ID ENABLE STARTTLS LOGINDISABLED SASL-IR] imap.example.org Cyrus IMAP 3.4.3 server ready\r\n",
+            "NGC6543 OK Begin TLS negotiation now\r\n",
+        ],
+        written: "NGC6543 STARTTLS\r\n",
+        response: "NGC6543 OK Begin TLS negotiation now",
+    },
+    Vector {
+        server: "Exchange",
+        script: &[
+            "* OK The Microsoft Exchange IMAP4 service is ready. [TQBXADIAUABSADAAMQBDAEEAMAAwADIA]\r\n",
+            "NGC6543 OK Begin TLS negotiation now.\r\n",
+        ],
+        written: "NGC6543 STARTTLS\r\n",
+        response: "NGC6543 OK Begin TLS negotiation now.",
+    },
+    Vector {
+        server: "Courier (untagged line before the tagged reply)",
+        script: &[
+            "* OK [CAPABILITY IMAP4rev1 UIDPLUS CHILDREN NAMESPACE THREAD=ORDEREDSUBJECT THREAD=REFERENCES SORT QUOTA IDLE ACL ACL2=UNION STARTTLS] Courier-IMAP ready. Copyright 1998-2018 Double Precision, Inc.  See COPYING for distribution information.\r\n",
+            "* OK [ALERT] Maintenance scheduled tonight\r\nNGC6543 OK Begin SSL/TLS negotiation now.\r\n",
+        ],
+        written: "NGC6543 STARTTLS\r\n",
+        response: "NGC6543 OK Begin SSL/TLS negotiation now.",
+    },
+];
+
+#[cfg(feature = "imap")]
+#[test]
+fn imap_servers() {
+    use io_starttls::imap::UpgradeTls;
+
+    for vector in IMAP {
+        for mut stream in vector.streams() {
+            let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+            let result = drive(&mut starttls, &mut stream);
+
+            assert_eq!(result, Ok(()), "{}", vector.server);
+            assert_eq!(
+                stream.written(),
+                vector.written.as_bytes(),
+                "{}",
+                vector.server
+            );
+            assert_eq!(
+                starttls.greeting(),
+                Some(vector.script[0]),
+                "{}",
+                vector.server
+            );
+            assert_eq!(
+                starttls.response(),
+                Some(vector.response),
+                "{}",
+                vector.server
+            );
+            assert!(stream.is_exhausted(), "{}", vector.server);
+        }
+    }
+}
+
+#[cfg(feature = "smtp")]
+const SMTP: &[Vector] = &[
+    Vector {
+        server: "Postfix",
+        script: &[
+            "220 mail.example.org ESMTP Postfix (Debian/GNU)\r\n",
+            "250-mail.example.org\r\n250-PIPELINING\r\n250-SIZE 10240000\r\n250-VRFY\r\n250-ETRN\r\n250-STARTTLS\r\n250-ENHANCEDSTATUSCODES\r\n250-8BITMIME\r\n250-DSN\r\n250-SMTPUTF8\r\n250 CHUNKING\r\n",
+            "220 2.0.0 Ready to start TLS\r\n",
+        ],
+        written: "EHLO localhost\r\nSTARTTLS\r\n",
+        response: "220 2.0.0 Ready to start TLS",
+    },
+    Vector {
+        server: "Gmail",
+        script: &[
+            "220 smtp.gmail.com ESMTP d9443c01a7336-2a1b2c3d4e5sm123456ad.1 - gsmtp\r\n",
+            "250-smtp.gmail.com at your service, [203.0.113.7]\r\n250-SIZE 35882577\r\n250-8BITMIME\r\n250-STARTTLS\r\n250-ENHANCEDSTATUSCODES\r\n250-PIPELINING\r\n250-CHUNKING\r\n250 SMTPUTF8\r\n",
+            "220 2.0.0 Ready to start TLS\r\n",
+        ],
+        written: "EHLO localhost\r\nSTARTTLS\r\n",
+        response: "220 2.0.0 Ready to start TLS",
+    },
+    Vector {
+        server: "Exchange",
+        script: &[
+            "220 mail.example.org Microsoft ESMTP MAIL Service ready at Mon, 1 Jan 2024 00:00:00 +0000\r\n",
+            "250-mail.example.org Hello [203.0.113.7]\r\n250-SIZE 37748736\r\n250-PIPELINING\r\n250-DSN\r\n250-ENHANCEDSTATUSCODES\r\n250-STARTTLS\r\n250-8BITMIME\r\n250-BINARYMIME\r\n250 CHUNKING\r\n",
+            "220 2.0.0 SMTP server ready\r\n",
+        ],
+        written: "EHLO localhost\r\nSTARTTLS\r\n",
+        response: "220 2.0.0 SMTP server ready",
+    },
+    Vector {
+        server: "Exim (multiline banner)",
+        script: &[
+            "220-mx.example.org ESMTP Exim 4.96 Mon, 01 Jan 2024 00:00:00 +0000\r\n220-We do not authorize the use of this system to transport unsolicited,\r\n220 and/or bulk e-mail.\r\n",
+            "250-mx.example.org Hello localhost [203.0.113.7]\r\n250-SIZE 52428800\r\n250-8BITMIME\r\n250-PIPELINING\r\n250-PIPE_CONNECT\r\n250-CHUNKING\r\n250-STARTTLS\r\n250 HELP\r\n",
+            "220 TLS go ahead\r\n",
+        ],
+        written: "EHLO localhost\r\nSTARTTLS\r\n",
+        response: "220 TLS go ahead",
+    },
+];
+
+#[cfg(feature = "smtp")]
+#[test]
+fn smtp_servers() {
+    use io_starttls::smtp::UpgradeTls;
+
+    for vector in SMTP {
+        for mut stream in vector.streams() {
+            let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+            let result = drive(&mut starttls, &mut stream);
+
+            assert_eq!(result, Ok(()), "{}", vector.server);
+            assert_eq!(
+                stream.written(),
+                vector.written.as_bytes(),
+                "{}",
+                vector.server
+            );
+            assert_eq!(
+                starttls.greeting(),
+                Some(vector.script[0]),
+                "{}",
+                vector.server
+            );
+            assert_eq!(
+                starttls.response(),
+                Some(vector.response),
+                "{}",
+                vector.server
+            );
+            assert!(stream.is_exhausted(), "{}", vector.server);
+        }
+    }
+}
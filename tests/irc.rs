@@ -0,0 +1,71 @@
+#![cfg(feature = "irc")]
+
+mod common;
+
+use common::{drive, drive_report, MockStream};
+use io_starttls::{irc::UpgradeTls, CompletionReport, Status, UpgradeTlsError};
+
+#[test]
+fn unsolicited_lines_before_numeric() {
+    let mut stream = MockStream::new([
+        ":irc.example.org NOTICE * :*** Looking up your hostname...\r\n",
+        "PING :irc.example.org\r\n:irc.example.org NOTICE * :*** 670 soon\r\n",
+        ":irc.example.org 670 * :STARTTLS successful, proceed with TLS handshake\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"STARTTLS\r\n");
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn rejected_numeric() {
+    let mut stream = MockStream::new([":irc.example.org 691 * :STARTTLS failed\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::Rejected {
+        status: "691".into(),
+        line: ":irc.example.org 691 * :STARTTLS failed".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn success_token() {
+    let response = ":irc.example.org 670 * :STARTTLS successful\r\n";
+
+    let mut stream = MockStream::new([response]);
+    let mut starttls = UpgradeTls::new().with_success_token("670");
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+
+    let mut stream = MockStream::new([response]);
+    let mut starttls = UpgradeTls::new().with_success_token("600");
+
+    let err = UpgradeTlsError::Rejected {
+        status: "670".into(),
+        line: ":irc.example.org 670 * :STARTTLS successful".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn report() {
+    let mut stream = MockStream::new([":irc.example.org 670 * :STARTTLS successful\r\n"]);
+    let mut starttls = UpgradeTls::new();
+    let report = drive_report(&mut starttls, &mut stream).unwrap();
+
+    assert_eq!(
+        report,
+        CompletionReport {
+            greeting: None,
+            response: ":irc.example.org 670 * :STARTTLS successful".into(),
+            status: Status::Code(670),
+            trailing_bytes: 0,
+            capabilities: Vec::new(),
+        }
+    );
+}
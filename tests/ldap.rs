@@ -0,0 +1,88 @@
+#![cfg(feature = "ldap")]
+
+mod common;
+
+use common::{drive, drive_report, MockStream};
+use io_starttls::{ldap::UpgradeTls, Status, UpgradeTlsError};
+
+/// Builds the StartTLS extended response with the given message ID
+/// and result code, without diagnostic message.
+fn response(id: u8, code: u8) -> Vec<u8> {
+    vec![
+        0x30, 0x0c, 0x02, 0x01, id, 0x78, 0x07, 0x0a, 0x01, code, 0x04, 0x00, 0x04, 0x00,
+    ]
+}
+
+#[test]
+fn success() {
+    let mut stream = MockStream::new([response(1, 0)]);
+    let mut starttls = UpgradeTls::new().with_strict_buffer(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+}
+
+#[test]
+fn report() {
+    let mut stream = MockStream::new([response(1, 0)]);
+    let mut starttls = UpgradeTls::new();
+
+    // binary responses have no status token
+    let report = drive_report(&mut starttls, &mut stream).unwrap();
+    assert_eq!(report.status, Status::None);
+}
+
+#[test]
+fn rejected() {
+    let mut stream = MockStream::new([response(1, 2)]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::Rejected {
+        status: "2".into(),
+        line: "".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn message_id() {
+    let mut stream = MockStream::new([response(7, 0)]);
+    let mut starttls = UpgradeTls::new().with_message_id(7);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+}
+
+#[test]
+fn message_id_mismatch() {
+    let mut stream = MockStream::new([response(2, 0)]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::InvalidResponse(response(2, 0));
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn message_id_too_large() {
+    // 0x0100000001 would be truncated to the expected message ID 1
+    let response = vec![
+        0x30, 0x10, 0x02, 0x05, 0x01, 0x00, 0x00, 0x00, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04,
+        0x00, 0x04, 0x00,
+    ];
+
+    let mut stream = MockStream::new([response.clone()]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::InvalidResponse(response);
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+
+    // a leading 0x00 only pads the sign bit
+    let response = vec![
+        0x30, 0x10, 0x02, 0x05, 0x00, 0x00, 0x00, 0x00, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04,
+        0x00, 0x04, 0x00,
+    ];
+
+    let mut stream = MockStream::new([response]);
+    let mut starttls = UpgradeTls::new();
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+}
@@ -0,0 +1,72 @@
+#![cfg(feature = "lmtp")]
+
+mod common;
+
+use common::{drive, MockStream};
+use io_starttls::{
+    lmtp::{Lmtp, UpgradeTls},
+    UpgradeTlsError,
+};
+
+#[test]
+fn lhlo_prelude() {
+    let mut stream = MockStream::new([
+        "220 localhost LMTP\r\n",
+        "250-localhost\r\n250-PIPELINING\r\n250 STARTTLS\r\n",
+        "220 Ready to start TLS\r\n",
+    ]);
+
+    let lmtp = Lmtp::default().with_lhlo("client.example.org");
+    let mut starttls = UpgradeTls::from_protocol(lmtp).with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"LHLO client.example.org\r\nSTARTTLS\r\n");
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn rejected_lhlo() {
+    let mut stream = MockStream::new(["220 LMTP\r\n", "500 unknown command\r\n"]);
+
+    let lmtp = Lmtp::default().with_lhlo("localhost");
+    let mut starttls = UpgradeTls::from_protocol(lmtp).with_discard_greeting(true);
+
+    let err = UpgradeTlsError::Rejected {
+        status: "500".into(),
+        line: "500 unknown command".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert_eq!(stream.written(), b"LHLO localhost\r\n");
+}
+
+#[test]
+fn lhlo_domain() {
+    let mut stream = MockStream::new([
+        "220 localhost LMTP\r\n",
+        "250 localhost\r\n",
+        "220 Ready to start TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_lhlo_domain("client.example.org");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"LHLO client.example.org\r\nSTARTTLS\r\n");
+}
+
+#[test]
+fn tls_required() {
+    let mut stream = MockStream::new([
+        "220 localhost LMTP\r\n",
+        "530 5.7.0 Must issue a STARTTLS command first\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_lhlo_domain("localhost");
+
+    assert!(drive(&mut starttls, &mut stream).is_err());
+    assert_eq!(starttls.tls_required(), Some(true));
+}
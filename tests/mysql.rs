@@ -0,0 +1,72 @@
+#![cfg(feature = "mysql")]
+
+mod common;
+
+use common::{drive, MockStream};
+use io_starttls::{mysql::UpgradeTls, UpgradeTlsError};
+
+/// Builds an initial handshake packet advertising the given
+/// capability flags (lower 2 bytes).
+fn handshake(flags: u16) -> Vec<u8> {
+    let mut payload = vec![10];
+    payload.extend(b"8.0.36\0");
+    payload.extend([1, 0, 0, 0]);
+    payload.extend([0x61; 8]);
+    payload.push(0);
+    payload.extend(flags.to_le_bytes());
+    payload.extend([45, 2, 0]);
+
+    let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+    packet.push(0);
+    packet.extend(payload);
+    packet
+}
+
+#[test]
+fn ssl_request_after_fragmented_handshake() {
+    let packet = handshake(0xffff);
+    let (header, payload) = packet.split_at(2);
+    let mut stream = MockStream::new([header, &payload[..5], &payload[5..]]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+
+    let written = stream.written();
+    assert_eq!(written.len(), 36);
+    assert_eq!(written[..4], [32, 0, 0, 1]);
+    assert_eq!(
+        u32::from_le_bytes(written[4..8].try_into().unwrap()) & 0x800,
+        0x800
+    );
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn ssl_not_supported() {
+    let mut stream = MockStream::new([handshake(0xf7ff)]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let err = UpgradeTlsError::StartTlsNotAdvertised;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert!(stream.written().is_empty());
+}
+
+#[test]
+fn error_packet() {
+    let mut payload = vec![0xff, 0x6a, 0x04];
+    payload.extend(b"#HY000Host is not allowed");
+
+    let mut packet = vec![payload.len() as u8, 0, 0, 0];
+    packet.extend(payload);
+
+    let mut stream = MockStream::new([packet]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    let err = UpgradeTlsError::Rejected {
+        status: "1130".into(),
+        line: "Host is not allowed".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "pop3")]
+
+mod common;
+
+use common::{drive, MockStream};
+use io_starttls::{pop3::UpgradeTls, UpgradeTlsError};
+
+#[test]
+fn capability_probe() {
+    let mut stream = MockStream::new([
+        "+OK POP3 ready\r\n",
+        "+OK capability list follows\r\nTOP\r\nSASL PLAIN\r\n",
+        "STLS\r\n.\r\n",
+        "+OK begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.capabilities(), ["TOP", "SASL PLAIN", "STLS"]);
+    assert_eq!(stream.written(), b"CAPA\r\nSTLS\r\n");
+}
+
+#[test]
+fn stls_not_advertised() {
+    let mut stream = MockStream::new(["+OK POP3 ready\r\n", "+OK\r\nTOP\r\n.\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    let err = UpgradeTlsError::StartTlsNotAdvertised;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert_eq!(stream.written(), b"CAPA\r\n");
+}
+
+#[test]
+fn capa_rejected() {
+    let mut stream = MockStream::new(["+OK POP3 ready\r\n", "-ERR unknown command\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_check_capability(true);
+
+    let err = UpgradeTlsError::Rejected {
+        status: "-ERR".into(),
+        line: "-ERR unknown command".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn lowercase_status() {
+    let mut stream = MockStream::new(["+OK POP3 ready\r\n", "+ok begin TLS\r\n"]);
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"STLS\r\n");
+}
+
+#[test]
+fn retries_in_use() {
+    let mut stream = MockStream::new([
+        "+OK POP3 ready\r\n",
+        "-ERR [IN-USE] mailbox locked\r\n",
+        "-ERR [SYS/TEMP] try again later\r\n",
+        "+OK begin TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_retries(2);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"STLS\r\nSTLS\r\nSTLS\r\n");
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn retries_skip_permanent_rejection() {
+    let mut stream = MockStream::new(["+OK POP3 ready\r\n", "-ERR [SYS/PERM] disabled\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_retries(2);
+
+    let err = UpgradeTlsError::Rejected {
+        status: "-ERR".into(),
+        line: "-ERR [SYS/PERM] disabled".into(),
+    };
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert_eq!(stream.written(), b"STLS\r\n");
+}
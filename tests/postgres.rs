@@ -0,0 +1,48 @@
+#![cfg(feature = "postgres")]
+
+mod common;
+
+use common::{drive, MockStream};
+use io_starttls::{postgres::UpgradeTls, UpgradeTlsError};
+
+#[test]
+fn proceed() {
+    let mut stream = MockStream::new(["S"]);
+    let mut starttls = UpgradeTls::new().with_strict_buffer(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        stream.written(),
+        [0x00, 0x00, 0x00, 0x08, 0x04, 0xD2, 0x16, 0x2F]
+    );
+}
+
+#[test]
+fn refuse() {
+    let mut stream = MockStream::new(["N"]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::Rejected {
+        status: "N".into(),
+        line: "N".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn bytes_injected_after_proceed() {
+    let mut stream = MockStream::new(["Sinjected"]);
+    let mut starttls = UpgradeTls::new().with_strict_buffer(true);
+
+    let err = UpgradeTlsError::TrailingBytes(b"injected".to_vec());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn success_token_ignored() {
+    let mut stream = MockStream::new(["S"]);
+    let mut starttls = UpgradeTls::new().with_success_token("OK");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::{drive, MockStream};
+use io_starttls::{Completion, StartTls, Upgrade, UpgradeTlsError};
+
+/// A binary protocol answering the upgrade request with a single
+/// byte, like Postgres.
+struct SingleByte;
+
+impl StartTls for SingleByte {
+    fn command(&self) -> &[u8] {
+        &[0x16]
+    }
+
+    fn is_greeting_complete(&mut self, bytes: &[u8]) -> Option<usize> {
+        (!bytes.is_empty()).then_some(1)
+    }
+
+    fn is_response_complete(&mut self, bytes: &[u8]) -> Option<Completion> {
+        let result = match bytes.first()? {
+            b'Y' => Ok(()),
+            byte => Err(UpgradeTlsError::InvalidResponse(vec![*byte])),
+        };
+
+        Some(Completion::new(1, result))
+    }
+}
+
+#[test]
+fn binary_protocol() {
+    let mut stream = MockStream::new([b"Y".as_slice()]);
+    let mut starttls = Upgrade::from_protocol(SingleByte);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), [0x16]);
+}
+
+#[test]
+fn binary_protocol_rejected() {
+    let mut stream = MockStream::new([b"N\n".as_slice()]);
+    let mut starttls = Upgrade::from_protocol(SingleByte);
+
+    let err = UpgradeTlsError::InvalidResponse(b"N".to_vec());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert_eq!(starttls.remaining(), b"\n");
+}
+
+#[cfg(all(feature = "imap", feature = "smtp", feature = "pop3"))]
+#[test]
+fn recommended_mode() {
+    use io_starttls::{recommended_mode, Mode, Protocol};
+
+    assert_eq!(recommended_mode(993, Protocol::Imap), Mode::Implicit);
+    assert_eq!(recommended_mode(143, Protocol::Imap), Mode::StartTls);
+    assert_eq!(recommended_mode(465, Protocol::Smtp), Mode::Implicit);
+    assert_eq!(recommended_mode(587, Protocol::Smtp), Mode::StartTls);
+    assert_eq!(recommended_mode(995, Protocol::Pop3), Mode::Implicit);
+    assert_eq!(recommended_mode(110, Protocol::Pop3), Mode::StartTls);
+
+    // implicit TLS ports are only meaningful for their own protocol
+    assert_eq!(recommended_mode(993, Protocol::Smtp), Mode::StartTls);
+}
+
+#[cfg(all(feature = "imap", feature = "smtp"))]
+#[test]
+fn upgrade_selected_at_runtime() {
+    use io_starttls::Protocol;
+
+    let mut stream = MockStream::new(["* OK ready\r\n", "NGC6543 OK begin TLS now\r\n"]);
+    let mut starttls = Protocol::Imap.upgrade().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"NGC6543 STARTTLS\r\n");
+    assert_eq!(starttls.protocol().name(), "Imap");
+
+    let mut stream = MockStream::new(["220 ready\r\n", "250 hello\r\n", "220 go ahead\r\n"]);
+    let mut starttls = io_starttls::upgrade(Protocol::Smtp).with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"EHLO localhost\r\nSTARTTLS\r\n");
+    assert_eq!(starttls.protocol().name(), "Smtp");
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "imap")]
+
+use io_starttls::{
+    imap::{Imap, UpgradeTls},
+    sans_io::{Flow, Step},
+    StartingState, UpgradeTlsError,
+};
+
+#[test]
+fn advance() {
+    let mut flow = UpgradeTls::new().with_discard_greeting(true).into_flow();
+
+    assert_eq!(flow.advance(b""), Step::NeedMore);
+    assert_eq!(flow.advance(b"* OK re"), Step::NeedMore);
+
+    let command = b"NGC6543 STARTTLS\r\n".to_vec();
+    assert_eq!(flow.advance(b"ady\r\n"), Step::Write(command));
+    assert_eq!(flow.advance(b""), Step::NeedMore);
+    assert_eq!(flow.advance(b"NGC6543 OK\r\n"), Step::Done(Ok(())));
+
+    assert_eq!(flow.greeting(), Some("* OK ready\r\n"));
+    assert_eq!(flow.response(), Some("NGC6543 OK"));
+    assert!(flow.remaining().is_empty());
+}
+
+#[test]
+fn response_buffered_with_greeting() {
+    let mut flow = UpgradeTls::new().with_discard_greeting(true).into_flow();
+
+    assert_eq!(flow.advance(b""), Step::NeedMore);
+
+    let command = b"NGC6543 STARTTLS\r\n".to_vec();
+    assert_eq!(
+        flow.advance(b"* OK\r\nNGC6543 OK\r\n"),
+        Step::Write(command)
+    );
+    assert_eq!(flow.advance(b""), Step::Done(Ok(())));
+}
+
+#[test]
+fn connection_closed() {
+    let mut flow = UpgradeTls::new().with_discard_greeting(true).into_flow();
+
+    assert_eq!(flow.advance(b""), Step::NeedMore);
+
+    let err = UpgradeTlsError::UnexpectedEof;
+    assert_eq!(flow.advance(b""), Step::Done(Err(err)));
+}
+
+#[test]
+fn advance_buffer() {
+    let mut flow = UpgradeTls::new().with_discard_greeting(true).into_flow();
+
+    assert_eq!(flow.advance(b""), Step::NeedMore);
+
+    let mut buffer = b"* OK re\0\0\0".to_vec();
+    assert_eq!(flow.advance_buffer(&mut buffer, 7), Step::NeedMore);
+    assert!(buffer.is_empty());
+
+    let mut buffer = b"ady\r\n\0\0\0".to_vec();
+    let command = b"NGC6543 STARTTLS\r\n".to_vec();
+    assert_eq!(flow.advance_buffer(&mut buffer, 5), Step::Write(command));
+    assert!(buffer.is_empty());
+
+    assert_eq!(flow.advance(b""), Step::NeedMore);
+
+    let mut buffer = b"NGC6543 OK\r\n* 1 EXISTS\r\n".to_vec();
+    assert_eq!(flow.advance_buffer(&mut buffer, 12), Step::Done(Ok(())));
+    assert_eq!(flow.greeting(), Some("* OK ready\r\n"));
+    assert!(flow.remaining().is_empty());
+}
+
+#[test]
+fn new() {
+    let mut flow = Flow::<Imap>::new()
+        .with_discard_greeting(true)
+        .with_command(b"A1 STARTTLS\r\n".to_vec());
+
+    assert_eq!(
+        flow.advance(b"* OK ready\r\n"),
+        Step::Write(b"A1 STARTTLS\r\n".to_vec())
+    );
+    assert_eq!(flow.advance(b""), Step::NeedMore);
+    assert_eq!(flow.advance(b"NGC6543 OK\r\n"), Step::Done(Ok(())));
+}
+
+#[test]
+fn from_protocol() {
+    let mut flow = Flow::from_protocol(Imap::default())
+        .with_starting_state(StartingState::DiscardResponse)
+        .with_max_reads(1);
+
+    assert_eq!(flow.advance(b""), Step::NeedMore);
+    assert_eq!(flow.advance(b"NGC6543 OK\r\n"), Step::Done(Ok(())));
+    assert_eq!(flow.response(), Some("NGC6543 OK"));
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "sieve")]
+
+mod common;
+
+use common::{drive, MockStream};
+use io_starttls::{sieve::UpgradeTls, UpgradeTlsError};
+
+#[test]
+fn starttls() {
+    let mut stream = MockStream::new([
+        "\"IMPLEMENTATION\" \"Dovecot Pigeonhole\"\r\n\"STARTTLS\"\r\nOK \"ready\"\r\n",
+        "OK \"begin TLS negotiation now\"\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"STARTTLS\r\n");
+}
+
+#[test]
+fn rejected() {
+    let mut stream = MockStream::new(["NO \"TLS not available\"\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::Rejected {
+        status: "NO".into(),
+        line: "NO \"TLS not available\"".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn bye() {
+    let mut stream = MockStream::new(["BYE \"too many connections\"\r\n"]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::ServerClosing("BYE \"too many connections\"".into());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
@@ -0,0 +1,389 @@
+#![cfg(feature = "smtp")]
+
+mod common;
+
+use common::{drive, drive_with, MockStream};
+use io_starttls::{
+    smtp::{Ehlo, UpgradeTls, DEFAULT_EHLO_DOMAIN},
+    UpgradeTlsError, DEFAULT_MAX_BUFFER,
+};
+
+#[test]
+fn ehlo_prelude() {
+    let mut stream = MockStream::new([
+        "220 mx.example.org ESMTP\r\n",
+        "250-mx.example.org\r\n250-PIPELINING\r\n250 STARTTLS\r\n",
+        "220 Ready to start TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true)
+        .with_prelude(vec![b"EHLO localhost\r\n".to_vec()]);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"EHLO localhost\r\nSTARTTLS\r\n");
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn rejected_prelude() {
+    let mut stream = MockStream::new(["220 ESMTP\r\n", "502 unknown command\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_prelude(vec![b"EHLO localhost\r\n".to_vec()]);
+
+    let err = UpgradeTlsError::Rejected {
+        status: "502".into(),
+        line: "502 unknown command".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert_eq!(stream.written(), b"EHLO localhost\r\n");
+}
+
+#[test]
+fn command_written_without_discarding_greeting() {
+    let mut stream = MockStream::new(["220 Ready to start TLS\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(false);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"STARTTLS\r\n");
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn multiline_response() {
+    let mut stream = MockStream::new([
+        "220 mx.example.org ESMTP\r\n",
+        "220-Ready to start TLS\r\n",
+        "220 Go ahead\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response(), Some("220 Go ahead"));
+    assert!(starttls.remaining().is_empty());
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn multiline_greeting_and_response_in_same_read() {
+    let mut stream = MockStream::new([
+        "220-mx.example.org ESMTP\r\n220 ready\r\n",
+        "220-Ready to start TLS\r\n220 Go ahead\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        starttls.greeting(),
+        Some("220-mx.example.org ESMTP\r\n220 ready\r\n")
+    );
+    assert_eq!(starttls.response(), Some("220 Go ahead"));
+}
+
+#[test]
+fn drain_multiline_greeting_lines() {
+    let chunks = [
+        "220-mx.example.org ESMTP\r\n",
+        "220-no UCE\r\n220-no spam\r\n",
+        "220 ready\r\n",
+        "220 Ready to start TLS\r\n",
+    ];
+
+    let mut stream = MockStream::new(chunks);
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true)
+        .with_max_buffer(32);
+
+    let err = UpgradeTlsError::LineTooLong;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+
+    let mut stream = MockStream::new(chunks);
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true)
+        .with_max_buffer(32)
+        .with_drain_lines(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.greeting(), Some("220 ready\r\n"));
+    assert_eq!(stream.written(), b"STARTTLS\r\n");
+}
+
+#[test]
+fn greeting_longer_than_read_capacity() {
+    let greeting = format!("220-{}\r\n220 ready\r\n", "x".repeat(40));
+    let mut stream = MockStream::new([greeting.as_str(), "220 Go ahead\r\n"]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true)
+        .with_read_capacity(16);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.greeting(), Some(greeting.as_str()));
+    assert_eq!(starttls.response(), Some("220 Go ahead"));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn greeting_complete() {
+    let mut stream = MockStream::new([
+        "220 appliance\r\n",
+        "firmware 1.2\r\n\r\n",
+        "220 Go ahead\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true)
+        .with_greeting_complete(|bytes| {
+            let n = bytes.windows(4).position(|w| w == b"\r\n\r\n")?;
+            Some(n + 4)
+        });
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        starttls.greeting(),
+        Some("220 appliance\r\nfirmware 1.2\r\n\r\n")
+    );
+    assert_eq!(starttls.response(), Some("220 Go ahead"));
+}
+
+#[test]
+fn greeting_complete_out_of_bounds() {
+    for end in [0, 64] {
+        let mut stream = MockStream::new(["220 appliance\r\n"]);
+        let mut starttls = UpgradeTls::new()
+            .with_discard_greeting(true)
+            .with_greeting_complete(move |_| Some(end));
+
+        assert!(matches!(
+            drive(&mut starttls, &mut stream),
+            Err(UpgradeTlsError::InvalidConfig(_))
+        ));
+        assert!(stream.written().is_empty());
+    }
+}
+
+#[test]
+fn already_secure() {
+    let mut stream = MockStream::new(["554 5.5.1 Error: TLS already active\r\n"]);
+    let mut starttls = UpgradeTls::new().with_ehlo_enabled(false);
+
+    let err = UpgradeTlsError::AlreadySecure("554 5.5.1 Error: TLS already active".into());
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+
+    let mut stream = MockStream::new(["454 TLS not available\r\n"]);
+    let mut starttls = UpgradeTls::new().with_ehlo_enabled(false);
+
+    let err = UpgradeTlsError::Rejected {
+        status: "454".into(),
+        line: "454 TLS not available".into(),
+    };
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn connection_closed_after_reply_without_newline() {
+    let mut stream = MockStream::new(["220 mx.example.org\r\n", "220 Go ahead", ""]);
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true);
+
+    let err = UpgradeTlsError::UnexpectedEof;
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn success_token() {
+    let mut stream = MockStream::new(["250-Go ahead\r\n250 with TLS\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_success_token("250");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.response(), Some("250 with TLS"));
+}
+
+#[test]
+fn retries_transient_reply() {
+    let mut stream = MockStream::new([
+        "220 ready\r\n",
+        "454 4.7.0 TLS not available due to temporary reason\r\n",
+        "220 go ahead\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true)
+        .with_retries(2);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"STARTTLS\r\nSTARTTLS\r\n");
+}
+
+#[test]
+fn ehlo_domain() {
+    let mut stream = MockStream::new([
+        "220 mx.example.org ESMTP\r\n",
+        "250-mx.example.org\r\n250 STARTTLS\r\n",
+        "220 Ready to start TLS\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_ehlo_domain("client.example.org");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"EHLO client.example.org\r\nSTARTTLS\r\n");
+    assert!(stream.is_exhausted());
+
+    // EHLO is sent with the default domain unless disabled
+    let mut stream = MockStream::new([
+        "220 mx.example.org ESMTP\r\n",
+        "250 mx.example.org\r\n",
+        "220 go ahead\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+    let ehlo = format!("EHLO {DEFAULT_EHLO_DOMAIN}\r\nSTARTTLS\r\n");
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), ehlo.as_bytes());
+
+    let mut stream = MockStream::new(["220 mx.example.org ESMTP\r\n", "220 go ahead\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_ehlo_enabled(false);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(stream.written(), b"STARTTLS\r\n");
+}
+
+#[test]
+fn ehlo_domain_with_line_terminators() {
+    let mut stream = MockStream::new::<_, &str>([]);
+    let mut starttls = UpgradeTls::new().with_ehlo_domain("x\r\nMAIL FROM:<a@example.org>");
+
+    assert!(matches!(
+        starttls.starting_state(),
+        Err(UpgradeTlsError::InvalidConfig(_))
+    ));
+    assert!(matches!(
+        drive(&mut starttls, &mut stream),
+        Err(UpgradeTlsError::InvalidConfig(_))
+    ));
+    assert!(stream.written().is_empty());
+
+    let starttls = UpgradeTls::new()
+        .with_ehlo_domain("client example")
+        .with_ehlo_enabled(false);
+    assert!(starttls.starting_state().is_ok());
+}
+
+#[test]
+fn ehlo_domain_rejected() {
+    let mut stream = MockStream::new([
+        "220 mx.example.org ESMTP\r\n",
+        "501 5.5.2 Syntax: EHLO hostname\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_ehlo_domain("192.0.2.1");
+
+    let err = UpgradeTlsError::Rejected {
+        status: "501".into(),
+        line: "501 5.5.2 Syntax: EHLO hostname".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+    assert_eq!(stream.written(), b"EHLO 192.0.2.1\r\n");
+}
+
+#[test]
+fn tls_required() {
+    let mut stream = MockStream::new([
+        "220 mx.example.org ESMTP\r\n",
+        "530 5.7.0 Must issue a STARTTLS command first\r\n",
+    ]);
+
+    let mut starttls = UpgradeTls::new()
+        .with_discard_greeting(true)
+        .with_prelude(vec![b"MAIL FROM:<alice@example.org>\r\n".to_vec()]);
+
+    assert!(drive(&mut starttls, &mut stream).is_err());
+    assert_eq!(starttls.tls_required(), Some(true));
+}
+
+#[test]
+fn tls_required_unknown() {
+    let mut stream = MockStream::new(["220 mx.example.org ESMTP\r\n", "220 go ahead\r\n"]);
+    let mut starttls = UpgradeTls::new()
+        .with_ehlo_enabled(false)
+        .with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(starttls.tls_required(), None);
+}
+
+#[test]
+fn ehlo_after_upgrade() {
+    let mut stream = MockStream::new(["250-mx.example.org\r\n", "250 AUTH PLAIN\r\n"]);
+
+    let mut ehlo = Ehlo::new("client.example.org");
+    let capabilities = drive_with(&mut stream, |io| ehlo.resume(io));
+
+    assert_eq!(capabilities, Ok(vec!["AUTH PLAIN".to_owned()]));
+    assert_eq!(stream.written(), b"EHLO client.example.org\r\n");
+}
+
+#[test]
+fn ehlo_connection_closed() {
+    let mut stream = MockStream::new(["250-mx.example.org\r\n", ""]);
+
+    let mut ehlo = Ehlo::new("client.example.org");
+    let result = drive_with(&mut stream, |io| ehlo.resume(io));
+
+    assert_eq!(result, Err(UpgradeTlsError::UnexpectedEof));
+    assert!(stream.is_exhausted());
+}
+
+#[test]
+fn ehlo_response_too_long() {
+    let mut stream = MockStream::new([vec![b'x'; DEFAULT_MAX_BUFFER + 1]]);
+
+    let mut ehlo = Ehlo::new("client.example.org");
+    let result = drive_with(&mut stream, |io| ehlo.resume(io));
+
+    assert_eq!(result, Err(UpgradeTlsError::LineTooLong));
+}
+
+#[test]
+fn ehlo_invalid_domain() {
+    // the coroutine fails before requesting any I/O
+    let mut ehlo = Ehlo::new("x\r\nMAIL FROM:<a@example.org>");
+    assert!(matches!(
+        ehlo.resume(None),
+        Ok(Err(UpgradeTlsError::InvalidConfig(_)))
+    ));
+
+    let mut ehlo = Ehlo::lhlo("");
+    assert!(matches!(
+        ehlo.resume(None),
+        Ok(Err(UpgradeTlsError::InvalidConfig(_)))
+    ));
+}
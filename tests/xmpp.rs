@@ -0,0 +1,56 @@
+#![cfg(feature = "xmpp")]
+
+mod common;
+
+use common::{drive, drive_report, MockStream};
+use io_starttls::{xmpp::UpgradeTls, CompletionReport, Status, UpgradeTlsError};
+
+const GREETING: &str = "<stream:stream from='example.org' version='1.0'>\
+    <stream:features><starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'/></stream:features>";
+
+#[test]
+fn proceed() {
+    let mut stream = MockStream::new([
+        GREETING,
+        "<proceed xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>",
+    ]);
+
+    let mut starttls = UpgradeTls::new().with_discard_greeting(true);
+
+    assert_eq!(drive(&mut starttls, &mut stream), Ok(()));
+    assert_eq!(
+        stream.written(),
+        b"<starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>"
+    );
+}
+
+#[test]
+fn failure() {
+    let mut stream = MockStream::new(["<failure xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>"]);
+    let mut starttls = UpgradeTls::new();
+
+    let err = UpgradeTlsError::Rejected {
+        status: "failure".into(),
+        line: "<failure xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>".into(),
+    };
+
+    assert_eq!(drive(&mut starttls, &mut stream), Err(err));
+}
+
+#[test]
+fn report() {
+    let mut stream = MockStream::new(["<proceed xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>"]);
+    let mut starttls = UpgradeTls::new();
+    let report = drive_report(&mut starttls, &mut stream).unwrap();
+
+    assert_eq!(
+        report,
+        CompletionReport {
+            greeting: None,
+            response: "<proceed xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>".into(),
+            status: Status::Keyword("proceed".into()),
+            trailing_bytes: 0,
+            capabilities: Vec::new(),
+        }
+    );
+}